@@ -0,0 +1,273 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::Arc;
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use log::error;
+use reqwest::Client;
+use serde::Serialize;
+use serde_json::Value;
+use crate::config::{parse_list, AppConfig};
+use crate::model::{EditionVersions, GeoIpLookupResult};
+use crate::state::{
+	FileService, MaxMindService, MaxMindServiceError, TimezoneService, VersionSelector,
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "GeoIP service")]
+pub struct Cli {
+	/// Config file (TOML or YAML), defaults to CONFIG_FILE environment variable
+	#[arg(long, global = true)]
+	pub config: Option<PathBuf>,
+	
+	#[command(subcommand)]
+	pub command: Option<CliCommand>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum CliCommand {
+	/// Start HTTP server (default)
+	Serve,
+	
+	/// Perform GeoIP lookup using databases from DATA_DIR
+	Lookup {
+		ip: IpAddr,
+		/// Defaults to first edition listed in the configuration
+		#[arg(long)]
+		edition: Option<String>,
+		/// Comma-separated editions to query and merge
+		#[arg(long)]
+		editions: Option<String>,
+		#[arg(long, default_value = "en")]
+		locale: String,
+		/// Use database versions which were the latest at given instant
+		#[arg(long, conflicts_with = "version")]
+		at: Option<DateTime<Utc>>,
+		/// Use database versions with given timestamp
+		#[arg(long)]
+		version: Option<DateTime<Utc>>,
+		/// Include current UTC offset and DST state of timezone
+		#[arg(long)]
+		timezone_details: bool,
+		#[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+		format: OutputFormat,
+	},
+	
+	/// Show status of databases from DATA_DIR
+	Status {
+		#[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+		format: OutputFormat,
+	},
+	
+	/// List retained versions of edition from DATA_DIR
+	Versions {
+		edition: String,
+		#[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+		format: OutputFormat,
+	},
+	
+	/// Pin edition to retained version, e.g. to roll back bad release
+	/// (running server picks it up on restart)
+	Pin {
+		edition: String,
+		/// Version timestamp as listed by versions command,
+		/// defaults to currently used version
+		timestamp: Option<DateTime<Utc>>,
+		#[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+		format: OutputFormat,
+	},
+	
+	/// Unpin edition, so its latest version is used again
+	/// (running server picks it up on restart)
+	Unpin {
+		edition: String,
+		#[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+		format: OutputFormat,
+	},
+	
+	/// Show metadata of MMDB file (or tar.gz archive containing one)
+	Inspect {
+		path: PathBuf,
+		#[arg(long, value_enum, default_value_t = OutputFormat::Table)]
+		format: OutputFormat,
+	},
+}
+
+impl CliCommand {
+	pub fn default_log_level(&self) -> &'static str {
+		match self {
+			CliCommand::Serve => "info",
+			_ => "warn",
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+	Json,
+	Table,
+}
+
+pub async fn run(command: CliCommand, config_file: Option<&Path>) -> ExitCode {
+	match command {
+		CliCommand::Serve => unreachable!("serve command must be handled by caller"),
+		CliCommand::Lookup { ip, edition, editions, locale, at, version, timezone_details, format } => {
+			let Some(maxmind) = load_maxmind(config_file).await else { return ExitCode::FAILURE };
+			let selector = at.map(VersionSelector::At).or(version.map(VersionSelector::Exact));
+			match lookup(&maxmind, ip, edition, editions, &locale, selector, timezone_details).await {
+				Some(res) => print(&res, format),
+				None => ExitCode::FAILURE,
+			}
+		},
+		CliCommand::Status { format } => {
+			let Some(maxmind) = load_maxmind(config_file).await else { return ExitCode::FAILURE };
+			print(&maxmind.status(), format)
+		},
+		CliCommand::Versions { edition, format } => {
+			let Some(maxmind) = load_maxmind(config_file).await else { return ExitCode::FAILURE };
+			print_versions(maxmind.list_versions(&edition), &edition, format)
+		},
+		CliCommand::Pin { edition, timestamp, format } => {
+			let Some(maxmind) = load_maxmind(config_file).await else { return ExitCode::FAILURE };
+			print_versions(maxmind.store_pin(&edition, timestamp).await, &edition, format)
+		},
+		CliCommand::Unpin { edition, format } => {
+			let Some(maxmind) = load_maxmind(config_file).await else { return ExitCode::FAILURE };
+			print_versions(maxmind.remove_pin(&edition).await, &edition, format)
+		},
+		CliCommand::Inspect { path, format } => {
+			match MaxMindService::inspect(&path) {
+				Ok(metadata) => print(&metadata, format),
+				Err(err) => {
+					error!("Unable to inspect {}: {err}", path.display());
+					ExitCode::FAILURE
+				}
+			}
+		},
+	}
+}
+
+/// Loads databases from DATA_DIR without creating, extracting, compiling
+/// or deleting anything there.
+async fn load_maxmind(config_file: Option<&Path>) -> Option<Arc<MaxMindService>> {
+	let config = match AppConfig::load(config_file) {
+		Ok(config) => config,
+		Err(err) => {
+			error!("{err}");
+			return None;
+		}
+	};
+	let client = Client::new();
+	let files = FileService::new_read_only(config.clone(), client).await;
+	let timezones = TimezoneService::new(config.clone(), files.clone()).await;
+	Some(MaxMindService::new(config, files, timezones).await)
+}
+
+async fn lookup(
+	maxmind: &MaxMindService,
+	ip: IpAddr,
+	edition: Option<String>,
+	editions: Option<String>,
+	locale: &str,
+	selector: Option<VersionSelector>,
+	timezone_details: bool,
+) -> Option<GeoIpLookupResult> {
+	let editions = editions.as_deref().map(parse_list).unwrap_or_default();
+	let editions = match maxmind.resolve_editions(
+		edition.as_deref(),
+		editions.iter().map(String::as_str).collect(),
+	) {
+		Ok(editions) => editions,
+		Err(err) => {
+			error!("{err}");
+			return None;
+		}
+	};
+	let start = std::time::Instant::now();
+	let res = match selector {
+		Some(selector) => {
+			maxmind.lookup_historical(ip, locale, &editions, selector, timezone_details).await
+		},
+		None => maxmind.lookup(ip, locale, &editions, timezone_details),
+	};
+	match res {
+		Ok(res) => Some(GeoIpLookupResult {
+			ip,
+			network: res.network,
+			info: res.info,
+			versions: res.versions,
+			elapsed: start.elapsed().as_secs_f64(),
+		}),
+		Err(err) => {
+			error!("Unable to lookup {ip}: {err}");
+			None
+		}
+	}
+}
+
+fn print_versions(
+	res: Result<EditionVersions, MaxMindServiceError>,
+	edition: &str,
+	format: OutputFormat,
+) -> ExitCode {
+	match res {
+		Ok(versions) => print(&versions, format),
+		Err(err) => {
+			error!("Unable to manage {edition} versions: {err}");
+			ExitCode::FAILURE
+		}
+	}
+}
+
+fn print<T: Serialize>(value: &T, format: OutputFormat) -> ExitCode {
+	let value = match serde_json::to_value(value) {
+		Ok(value) => value,
+		Err(err) => {
+			error!("Unable to serialize output: {err}");
+			return ExitCode::FAILURE;
+		}
+	};
+	match format {
+		OutputFormat::Json => {
+			match serde_json::to_string_pretty(&value) {
+				Ok(json) => println!("{json}"),
+				Err(err) => {
+					error!("Unable to serialize output: {err}");
+					return ExitCode::FAILURE;
+				}
+			}
+		},
+		OutputFormat::Table => {
+			let mut rows = Vec::new();
+			flatten(None, &value, &mut rows);
+			let width = rows.iter().map(|(key, _)| key.len()).max().unwrap_or(0);
+			for (key, value) in rows {
+				println!("{key:width$}  {value}");
+			}
+		},
+	}
+	ExitCode::SUCCESS
+}
+
+fn flatten(prefix: Option<&str>, value: &Value, out: &mut Vec<(String, String)>) {
+	match value {
+		Value::Array(items) => {
+			for (i, item) in items.iter().enumerate() {
+				flatten(Some(&format!("{}[{i}]", prefix.unwrap_or(""))), item, out);
+			}
+		},
+		Value::Object(fields) => {
+			for (key, item) in fields {
+				let key = match prefix {
+					Some(prefix) => format!("{prefix}.{key}"),
+					None => key.clone(),
+				};
+				flatten(Some(&key), item, out);
+			}
+		},
+		Value::String(s) => out.push((prefix.unwrap_or("").to_owned(), s.clone())),
+		Value::Null => out.push((prefix.unwrap_or("").to_owned(), "-".to_owned())),
+		value => out.push((prefix.unwrap_or("").to_owned(), value.to_string())),
+	}
+}
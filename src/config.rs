@@ -1,13 +1,290 @@
+use std::collections::BTreeMap;
 use std::env;
-use std::net::SocketAddr;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::fmt::Display;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
+use ahash::AHashMap;
+use constant_time_eq::constant_time_eq;
+use ipnetwork::IpNetwork;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
 
 const DEFAULT_EDITIONS: &str = "GeoLite2-City";
+const DEFAULT_MAX_BATCH_SIZE: usize = 1000;
+const DEFAULT_CAPTCHA_MIN_SCORE: f32 = 0.5;
+const DEFAULT_CAPTCHA_ACTION: &str = "submit";
+const DEFAULT_CAPTCHA_CACHE_TTL: u64 = 120;
+const DEFAULT_CAPTCHA_CACHE_SIZE: usize = 10000;
+const DEFAULT_CLIENT_IP_HEADERS: &str = "cf-connecting-ip,forwarded,x-forwarded-for";
+const DEFAULT_RETRY_INITIAL_DELAY: u64 = 60;
+const DEFAULT_RETRY_MAX_DELAY: u64 = 3600;
+const DEFAULT_ARCHIVE_RETENTION: usize = 3;
+const DEFAULT_HISTORICAL_READERS: usize = 4;
+const DEFAULT_RATE_LIMIT_IPV6_PREFIX: u8 = 64;
 pub const DOWNLOAD_URL_EDITION_PLACEHOLDER: &str = "{edition}";
 const DOWNLOAD_URL: &str = "https://download.maxmind.com/geoip/databases/{edition}/download?suffix=tar.gz";
+const CHECKSUM_URL: &str = "https://download.maxmind.com/geoip/databases/{edition}/download?suffix=tar.gz.sha256";
+const API_KEYS_FILE_NAME: &str = "api_keys.toml";
+const DEFAULT_API_KEY_NAME: &str = "default";
 const TZDATA_DOWNLOAD_URL: &str = "https://data.iana.org/time-zones/tzdata-latest.tar.gz";
 
+static EDITION_PATTERN: LazyLock<Regex> = LazyLock::new(|| Regex::new(
+	"^[A-Za-z0-9-]+$"
+).expect("Unable to compile regex"));
+
+#[derive(Debug, Error)]
+pub enum AppConfigError {
+	#[error("Unable to read config file {0}: {1}")]
+	Io(PathBuf, std::io::Error),
+	
+	#[error("Unable to parse config file {0}: {1}")]
+	Parse(PathBuf, String),
+	
+	#[error("Invalid configuration:\n- {}", .0.join("\n- "))]
+	Invalid(Vec<String>),
+}
+
+/// Per-edition overrides of global MaxMind settings.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EditionConfig {
+	pub download_url: Option<String>,
+	pub checksum_url: Option<String>,
+	pub auth: Option<EditionAuth>,
+	pub account_id: Option<String>,
+	pub licence_key: Option<String>,
+	pub bearer_token: Option<String>,
+	pub auto_update_interval: Option<u64>,
+	pub retention: Option<usize>,
+}
+
+/// Explicit choice of download authorization for an edition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EditionAuth {
+	None,
+	Bearer,
+	Basic,
+}
+
+impl FromStr for EditionAuth {
+	type Err = String;
+	
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"none" => Ok(Self::None),
+			"bearer" => Ok(Self::Bearer),
+			"basic" => Ok(Self::Basic),
+			_ => Err(format!("unknown auth \"{s}\"")),
+		}
+	}
+}
+
+/// Permission granted to API key, also used as route group
+/// for rate limiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+pub enum ApiScope {
+	#[serde(rename = "geoip:lookup")]
+	GeoIpLookup,
+	#[serde(rename = "files:mmdb")]
+	FilesMmdb,
+	#[serde(rename = "files:tzdata")]
+	FilesTzdata,
+	#[serde(rename = "timezones")]
+	Timezones,
+	#[serde(rename = "admin")]
+	Admin,
+}
+
+impl ApiScope {
+	pub const ALL: [ApiScope; 5] = [
+		ApiScope::GeoIpLookup,
+		ApiScope::FilesMmdb,
+		ApiScope::FilesTzdata,
+		ApiScope::Timezones,
+		ApiScope::Admin,
+	];
+	
+	pub fn name(&self) -> &'static str {
+		match self {
+			ApiScope::GeoIpLookup => "geoip:lookup",
+			ApiScope::FilesMmdb => "files:mmdb",
+			ApiScope::FilesTzdata => "files:tzdata",
+			ApiScope::Timezones => "timezones",
+			ApiScope::Admin => "admin",
+		}
+	}
+}
+
+/// Token bucket settings written as `{requests}/{seconds}`
+/// (e.g. `100/60`), bucket holds up to `requests` tokens
+/// refilled evenly over `seconds`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub struct RateLimit {
+	pub requests: u32,
+	pub period: u64,
+}
+
+impl FromStr for RateLimit {
+	type Err = String;
+	
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (requests, period) = s.split_once('/')
+			.ok_or_else(|| format!("expected {{requests}}/{{seconds}}, got \"{s}\""))?;
+		let requests = requests.trim().parse().map_err(|err| format!("invalid requests: {err}"))?;
+		let period = period.trim().parse().map_err(|err| format!("invalid seconds: {err}"))?;
+		if requests == 0 || period == 0 {
+			return Err("requests and seconds must be positive".to_owned());
+		}
+		Ok(Self { requests, period })
+	}
+}
+
+impl TryFrom<String> for RateLimit {
+	type Error = String;
+	
+	fn try_from(value: String) -> Result<Self, Self::Error> {
+		value.parse()
+	}
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeyConfig {
+	pub key: String,
+	pub scopes: Vec<ApiScope>,
+	#[serde(default)]
+	pub rate_limits: BTreeMap<ApiScope, RateLimit>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ApiKey {
+	pub name: Arc<str>,
+	pub key: String,
+	pub scopes: Vec<ApiScope>,
+	/// Overrides of global rate limits.
+	pub rate_limits: BTreeMap<ApiScope, RateLimit>,
+}
+
+/// Captcha service verifying tokens of anonymous users.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptchaProvider {
+	Recaptcha,
+	Turnstile,
+	HCaptcha,
+}
+
+impl FromStr for CaptchaProvider {
+	type Err = String;
+	
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"recaptcha" => Ok(Self::Recaptcha),
+			"turnstile" => Ok(Self::Turnstile),
+			"hcaptcha" => Ok(Self::HCaptcha),
+			_ => Err(format!("unknown captcha provider \"{s}\"")),
+		}
+	}
+}
+
+/// Request header carrying original client address,
+/// set by reverse proxy or CDN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientIpHeader {
+	CfConnectingIp,
+	Forwarded,
+	XForwardedFor,
+	XRealIp,
+	TrueClientIp,
+}
+
+impl ClientIpHeader {
+	pub fn name(&self) -> &'static str {
+		match self {
+			Self::CfConnectingIp => "cf-connecting-ip",
+			Self::Forwarded => "forwarded",
+			Self::XForwardedFor => "x-forwarded-for",
+			Self::XRealIp => "x-real-ip",
+			Self::TrueClientIp => "true-client-ip",
+		}
+	}
+}
+
+impl FromStr for ClientIpHeader {
+	type Err = String;
+	
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_ascii_lowercase().as_str() {
+			"cf-connecting-ip" => Ok(Self::CfConnectingIp),
+			"forwarded" => Ok(Self::Forwarded),
+			"x-forwarded-for" => Ok(Self::XForwardedFor),
+			"x-real-ip" => Ok(Self::XRealIp),
+			"true-client-ip" => Ok(Self::TrueClientIp),
+			_ => Err(format!("unsupported header \"{s}\"")),
+		}
+	}
+}
+
+/// Config file contents, every field mirrors environment variable
+/// with the same name in upper case.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+	listen_addr: Option<SocketAddr>,
+	data_dir: Option<PathBuf>,
+	maxmind_account_id: Option<String>,
+	maxmind_licence_key: Option<String>,
+	maxmind_bearer_token: Option<String>,
+	maxmind_editions: Option<Vec<String>>,
+	maxmind_merge_editions: Option<Vec<String>>,
+	maxmind_download_url: Option<String>,
+	maxmind_checksum_url: Option<String>,
+	auto_update_interval: Option<u64>,
+	retry_initial_delay: Option<u64>,
+	retry_max_delay: Option<u64>,
+	archive_retention: Option<usize>,
+	historical_readers: Option<usize>,
+	api_key: Option<String>,
+	rate_limit_ipv6_prefix: Option<u8>,
+	max_batch_size: Option<usize>,
+	trusted_proxies: Option<Vec<String>>,
+	proxy_protocol: Option<bool>,
+	proxy_protocol_trusted: Option<Vec<String>>,
+	client_ip_headers: Option<Vec<String>>,
+	captcha_provider: Option<CaptchaProvider>,
+	captcha_site_key: Option<String>,
+	captcha_secret_key: Option<String>,
+	recaptcha_site_key: Option<String>,
+	recaptcha_secret_key: Option<String>,
+	captcha_min_score: Option<f32>,
+	captcha_action: Option<String>,
+	captcha_hostnames: Option<Vec<String>>,
+	recaptcha_min_score: Option<f32>,
+	recaptcha_action: Option<String>,
+	recaptcha_hostnames: Option<Vec<String>>,
+	captcha_cache_ttl: Option<u64>,
+	captcha_cache_size: Option<usize>,
+	captcha_session_ttl: Option<u64>,
+	captcha_session_secret: Option<String>,
+	captcha_session_secure: Option<bool>,
+	osm_tiles_url: Option<String>,
+	tzdata_auto_update_interval: Option<u64>,
+	tzdata_download_url: Option<String>,
+	tzdata_bearer_token: Option<String>,
+	zic_path: Option<String>,
+	canonical_timezones: Option<bool>,
+	google_tag_id: Option<String>,
+	editions: BTreeMap<String, EditionConfig>,
+	api_keys: BTreeMap<String, ApiKeyConfig>,
+	rate_limits: BTreeMap<ApiScope, RateLimit>,
+}
+
 pub struct AppConfig {
 	pub listen_addr: SocketAddr,
 	pub data_dir: PathBuf,
@@ -15,79 +292,627 @@ pub struct AppConfig {
 	pub maxmind_license_key: Option<String>,
 	pub maxmind_bearer_token: Option<String>,
 	pub maxmind_editions: Vec<String>,
+	pub maxmind_merge_editions: Vec<String>,
 	pub maxmind_download_url: String,
+	/// Empty if checksum verification is disabled.
+	pub maxmind_checksum_url: String,
+	pub maxmind_edition_configs: AHashMap<String, EditionConfig>,
 	pub auto_update: bool,
 	pub auto_update_interval: u64,
-	pub api_key: Option<String>,
-	pub recaptcha_site_key: Option<String>,
-	pub recaptcha_secret_key: Option<String>,
+	pub retry_initial_delay: u64,
+	pub retry_max_delay: u64,
+	/// Number of newest archives kept per edition (and tzdata), all if zero,
+	/// pinned archive is kept regardless.
+	pub archive_retention: usize,
+	/// Number of opened historical database versions kept in memory.
+	pub historical_readers: usize,
+	/// Named keys from config file, keys file and `API_KEY`
+	/// (named "default", with all scopes).
+	pub api_keys: Vec<ApiKey>,
+	/// Rate limits of route groups applied per API key
+	/// or per client IP for anonymous requests.
+	pub rate_limits: BTreeMap<ApiScope, RateLimit>,
+	/// Anonymous IPv6 clients share bucket of network with this prefix.
+	pub rate_limit_ipv6_prefix: u8,
+	pub max_batch_size: usize,
+	pub trusted_proxies: Vec<IpNetwork>,
+	pub proxy_protocol: bool,
+	/// Load balancers allowed to send PROXY protocol header, kept apart
+	/// from `trusted_proxies` trusted with client address headers.
+	pub proxy_protocol_trusted: Vec<IpNetwork>,
+	pub client_ip_headers: Vec<ClientIpHeader>,
+	pub captcha_provider: CaptchaProvider,
+	pub captcha_site_key: Option<String>,
+	pub captcha_secret_key: Option<String>,
+	/// Minimum score, tokens without score are rejected only if set explicitly.
+	pub captcha_min_score: Option<f32>,
+	/// Expected action, tokens without action are rejected only if set
+	/// explicitly, not checked if empty.
+	pub captcha_action: Option<String>,
+	/// Allowed hostnames, any if empty.
+	pub captcha_hostnames: Vec<String>,
+	/// Seconds verification results are cached for, caching is disabled if zero.
+	pub captcha_cache_ttl: u64,
+	pub captcha_cache_size: usize,
+	/// Seconds captcha session cookie is valid for, no cookie is issued if zero.
+	pub captcha_session_ttl: u64,
+	/// Key signing session cookies, random on every start if not set.
+	pub captcha_session_secret: Option<String>,
+	/// Mark session cookie `Secure`, disable only for plain HTTP development.
+	pub captcha_session_secure: bool,
 	pub osm_tiles_url: Option<String>,
 	pub tzdata_auto_update_interval: Option<u64>,
 	pub tzdata_download_url: String,
 	pub tzdata_bearer_token: Option<String>,
 	pub zic_path: Option<String>,
+	/// Report canonical timezone id instead of alias in lookup results.
+	pub canonical_timezones: bool,
 	pub google_tag_id: Option<String>,
 }
 
 impl AppConfig {
-	pub fn load_from_env() -> Arc<Self> {
-		let listen_addr = env::var("LISTEN_ADDR").ok()
-			.unwrap_or_else(|| "127.0.0.1:8080".to_owned())
-			.parse()
-			.expect("LISTEN_ADDR must be a valid socket address");
-		let data_dir = env::var("DATA_DIR").ok()
+	/// Loads configuration from optional config file (TOML or YAML,
+	/// `CONFIG_FILE` environment variable is used if `config_file`
+	/// is not specified), environment variables override file values.
+	pub fn load(config_file: Option<&Path>) -> Result<Arc<Self>, AppConfigError> {
+		let config_file = config_file
+			.map(Path::to_path_buf)
+			.or_else(|| env::var("CONFIG_FILE").ok().map(PathBuf::from));
+		let file = match &config_file {
+			Some(path) => Self::read_file(path)?,
+			None => FileConfig::default(),
+		};
+		Self::build(file, &|name| env::var(name).ok())
+	}
+	
+	/// Loads configuration from TOML string ignoring environment variables,
+	/// so that tests don't depend on the environment they run in.
+	#[cfg(test)]
+	pub fn from_toml(content: &str) -> Result<Arc<Self>, AppConfigError> {
+		let file = toml::from_str(content)
+			.map_err(|err| AppConfigError::Parse(PathBuf::new(), err.to_string()))?;
+		Self::build(file, &|_| None)
+	}
+	
+	/// Builds configuration from config file contents overridden
+	/// by variables returned by `var`.
+	fn build(
+		file: FileConfig,
+		var: &dyn Fn(&str) -> Option<String>,
+	) -> Result<Arc<Self>, AppConfigError> {
+		let mut errors = Vec::new();
+		
+		let listen_addr = env_or(
+			&mut errors,
+			var,
+			"LISTEN_ADDR",
+			"a valid socket address",
+			file.listen_addr,
+		).unwrap_or_else(|| ([127, 0, 0, 1], 8080).into());
+		let data_dir = var("DATA_DIR")
 			.map(PathBuf::from)
-			.expect("DATA_DIR must be set");
-		let maxmind_account_id = env::var("MAXMIND_ACCOUNT_ID").ok();
-		let maxmind_license_key = env::var("MAXMIND_LICENCE_KEY").ok();
-		let maxmind_bearer_token = env::var("MAXMIND_BEARER_TOKEN").ok();
-		let maxmind_editions = env::var("MAXMIND_EDITIONS").ok()
-			.unwrap_or_else(|| DEFAULT_EDITIONS.to_owned())
-			.split(',')
-			.map(str::trim)
-			.map(str::to_owned)
-			.collect();
-		let maxmind_download_url = env::var("MAXMIND_DOWNLOAD_URL").ok();
+			.or(file.data_dir);
+		if data_dir.is_none() {
+			errors.push("DATA_DIR must be set".to_owned());
+		}
+		let maxmind_account_id = var("MAXMIND_ACCOUNT_ID").or(file.maxmind_account_id);
+		let maxmind_license_key = var("MAXMIND_LICENCE_KEY").or(file.maxmind_licence_key);
+		let maxmind_bearer_token = var("MAXMIND_BEARER_TOKEN").or(file.maxmind_bearer_token);
+		let maxmind_editions = var("MAXMIND_EDITIONS")
+			.map(|v| parse_list(&v))
+			.or(file.maxmind_editions)
+			.unwrap_or_else(|| parse_list(DEFAULT_EDITIONS));
+		let maxmind_merge_editions = var("MAXMIND_MERGE_EDITIONS")
+			.map(|v| parse_list(&v))
+			.or(file.maxmind_merge_editions)
+			.unwrap_or_default();
+		let maxmind_download_url = var("MAXMIND_DOWNLOAD_URL").or(file.maxmind_download_url);
+		let mut maxmind_edition_configs: AHashMap<_, _> = file.editions.into_iter().collect();
+		for edition in &maxmind_editions {
+			let config = maxmind_edition_configs.remove(edition).unwrap_or_default();
+			let config = edition_config_from_env(&mut errors, var, edition, config);
+			maxmind_edition_configs.insert(edition.clone(), config);
+		}
+		let maxmind_checksum_url = var("MAXMIND_CHECKSUM_URL")
+			.or(file.maxmind_checksum_url)
+			.unwrap_or_else(|| match maxmind_download_url {
+				Some(_) => String::new(),
+				None => CHECKSUM_URL.to_owned(),
+			});
 		let auto_update = maxmind_account_id.is_some() || maxmind_download_url.is_some();
 		let maxmind_download_url = maxmind_download_url
 			.unwrap_or_else(|| DOWNLOAD_URL.to_owned());
-		let auto_update_interval = env::var("AUTO_UPDATE_INTERVAL").ok()
-			.unwrap_or_else(|| "24".to_owned())
-			.parse()
-			.expect("AUTO_UPDATE_INTERVAL must be a valid integer");
-		let api_key = env::var("API_KEY").ok();
-		let recaptcha_site_key = env::var("RECAPTCHA_SITE_KEY").ok();
-		let recaptcha_secret_key = env::var("RECAPTCHA_SECRET_KEY").ok();
-		let osm_tiles_url = env::var("OSM_TILES_URL").ok();
-		let tzdata_auto_update_interval = env::var("TZDATA_AUTO_UPDATE_INTERVAL").ok()
-			.unwrap_or_else(|| "24".to_owned())
-			.parse()
-			.expect("TZDATA_AUTO_UPDATE_INTERVAL must be a valid integer");
-		let tzdata_download_url = env::var("TZDATA_DOWNLOAD_URL").ok()
+		let auto_update_interval = env_or(
+			&mut errors,
+			var,
+			"AUTO_UPDATE_INTERVAL",
+			"a valid integer",
+			file.auto_update_interval,
+		).unwrap_or(24);
+		let retry_initial_delay = env_or(
+			&mut errors,
+			var,
+			"RETRY_INITIAL_DELAY",
+			"a valid integer",
+			file.retry_initial_delay,
+		).unwrap_or(DEFAULT_RETRY_INITIAL_DELAY);
+		let retry_max_delay = env_or(
+			&mut errors,
+			var,
+			"RETRY_MAX_DELAY",
+			"a valid integer",
+			file.retry_max_delay,
+		).unwrap_or(DEFAULT_RETRY_MAX_DELAY);
+		let archive_retention = env_or(
+			&mut errors,
+			var,
+			"ARCHIVE_RETENTION",
+			"a valid integer",
+			file.archive_retention,
+		).unwrap_or(DEFAULT_ARCHIVE_RETENTION);
+		let historical_readers = env_or(
+			&mut errors,
+			var,
+			"HISTORICAL_READERS",
+			"a valid integer",
+			file.historical_readers,
+		).unwrap_or(DEFAULT_HISTORICAL_READERS);
+		let api_key = var("API_KEY").or(file.api_key);
+		let mut api_key_configs: Vec<_> = file.api_keys.into_iter().collect();
+		if let Some(data_dir) = &data_dir {
+			let keys_file = data_dir.join(API_KEYS_FILE_NAME);
+			if keys_file.exists() {
+				match Self::read_file::<BTreeMap<String, ApiKeyConfig>>(&keys_file) {
+					Ok(keys) => api_key_configs.extend(keys),
+					Err(err) => errors.push(err.to_string()),
+				}
+			}
+		}
+		if let Some(key) = api_key {
+			api_key_configs.push((DEFAULT_API_KEY_NAME.to_owned(), ApiKeyConfig {
+				key,
+				scopes: ApiScope::ALL.to_vec(),
+				rate_limits: BTreeMap::new(),
+			}));
+		}
+		let api_keys = api_key_configs.into_iter()
+			.map(|(name, config)| ApiKey {
+				name: name.into(),
+				key: config.key,
+				scopes: config.scopes,
+				rate_limits: config.rate_limits,
+			})
+			.collect();
+		let mut rate_limits = file.rate_limits;
+		for scope in ApiScope::ALL {
+			let name = format!("RATE_LIMIT_{}", scope.name().to_uppercase().replace(':', "_"));
+			let rate_limit = env_or(
+				&mut errors,
+				var,
+				&name,
+				"{requests}/{seconds}",
+				rate_limits.remove(&scope),
+			);
+			rate_limits.extend(rate_limit.map(|rate_limit| (scope, rate_limit)));
+		}
+		let rate_limit_ipv6_prefix = env_or(
+			&mut errors,
+			var,
+			"RATE_LIMIT_IPV6_PREFIX",
+			"a valid integer",
+			file.rate_limit_ipv6_prefix,
+		).unwrap_or(DEFAULT_RATE_LIMIT_IPV6_PREFIX);
+		let max_batch_size = env_or(
+			&mut errors,
+			var,
+			"MAX_BATCH_SIZE",
+			"a valid integer",
+			file.max_batch_size,
+		).unwrap_or(DEFAULT_MAX_BATCH_SIZE);
+		let trusted_proxies = var("TRUSTED_PROXIES")
+			.map(|v| parse_list(&v))
+			.or(file.trusted_proxies)
+			.unwrap_or_default();
+		let trusted_proxies = parse_items(&mut errors, "TRUSTED_PROXIES", "CIDR", trusted_proxies);
+		let proxy_protocol = env_or(
+			&mut errors,
+			var,
+			"PROXY_PROTOCOL",
+			"true or false",
+			file.proxy_protocol,
+		).unwrap_or(false);
+		let proxy_protocol_trusted = var("PROXY_PROTOCOL_TRUSTED")
+			.map(|v| parse_list(&v))
+			.or(file.proxy_protocol_trusted)
+			.unwrap_or_default();
+		let proxy_protocol_trusted = parse_items(
+			&mut errors,
+			"PROXY_PROTOCOL_TRUSTED",
+			"CIDR",
+			proxy_protocol_trusted,
+		);
+		let client_ip_headers = var("CLIENT_IP_HEADERS")
+			.map(|v| parse_list(&v))
+			.or(file.client_ip_headers)
+			.unwrap_or_else(|| parse_list(DEFAULT_CLIENT_IP_HEADERS));
+		let client_ip_headers = parse_items(
+			&mut errors,
+			"CLIENT_IP_HEADERS",
+			"header",
+			client_ip_headers,
+		);
+		let captcha_provider = env_or(
+			&mut errors,
+			var,
+			"CAPTCHA_PROVIDER",
+			"recaptcha, turnstile or hcaptcha",
+			file.captcha_provider,
+		).unwrap_or(CaptchaProvider::Recaptcha);
+		// RECAPTCHA_* names are kept for compatibility
+		let captcha_site_key = var("CAPTCHA_SITE_KEY")
+			.or_else(|| var("RECAPTCHA_SITE_KEY"))
+			.or(file.captcha_site_key)
+			.or(file.recaptcha_site_key);
+		let captcha_secret_key = var("CAPTCHA_SECRET_KEY")
+			.or_else(|| var("RECAPTCHA_SECRET_KEY"))
+			.or(file.captcha_secret_key)
+			.or(file.recaptcha_secret_key);
+		let recaptcha_min_score = env_or(
+			&mut errors,
+			var,
+			"RECAPTCHA_MIN_SCORE",
+			"a valid number",
+			file.captcha_min_score.or(file.recaptcha_min_score),
+		);
+		let captcha_min_score = env_or(
+			&mut errors,
+			var,
+			"CAPTCHA_MIN_SCORE",
+			"a valid number",
+			recaptcha_min_score,
+		);
+		let captcha_action = var("CAPTCHA_ACTION")
+			.or_else(|| var("RECAPTCHA_ACTION"))
+			.or(file.captcha_action)
+			.or(file.recaptcha_action);
+		let captcha_hostnames = var("CAPTCHA_HOSTNAMES")
+			.or_else(|| var("RECAPTCHA_HOSTNAMES"))
+			.map(|v| parse_list(&v))
+			.or(file.captcha_hostnames)
+			.or(file.recaptcha_hostnames)
+			.unwrap_or_default();
+		let captcha_cache_ttl = env_or(
+			&mut errors,
+			var,
+			"CAPTCHA_CACHE_TTL",
+			"a valid integer",
+			file.captcha_cache_ttl,
+		).unwrap_or(DEFAULT_CAPTCHA_CACHE_TTL);
+		let captcha_cache_size = env_or(
+			&mut errors,
+			var,
+			"CAPTCHA_CACHE_SIZE",
+			"a valid integer",
+			file.captcha_cache_size,
+		).unwrap_or(DEFAULT_CAPTCHA_CACHE_SIZE);
+		let captcha_session_ttl = env_or(
+			&mut errors,
+			var,
+			"CAPTCHA_SESSION_TTL",
+			"a valid integer",
+			file.captcha_session_ttl,
+		).unwrap_or(0);
+		let captcha_session_secret = var("CAPTCHA_SESSION_SECRET")
+			.or(file.captcha_session_secret);
+		let captcha_session_secure = env_or(
+			&mut errors,
+			var,
+			"CAPTCHA_SESSION_SECURE",
+			"true or false",
+			file.captcha_session_secure,
+		).unwrap_or(true);
+		let osm_tiles_url = var("OSM_TILES_URL").or(file.osm_tiles_url);
+		let tzdata_auto_update_interval = env_or(
+			&mut errors,
+			var,
+			"TZDATA_AUTO_UPDATE_INTERVAL",
+			"a valid integer",
+			file.tzdata_auto_update_interval,
+		).unwrap_or(24);
+		let tzdata_download_url = var("TZDATA_DOWNLOAD_URL")
+			.or(file.tzdata_download_url)
 			.unwrap_or_else(|| TZDATA_DOWNLOAD_URL.to_owned());
-		let tzdata_bearer_token = env::var("TZDATA_BEARER_TOKEN").ok();
-		let zic_path = env::var("ZIC_PATH").ok();
-		let google_tag_id = env::var("GOOGLE_TAG_ID").ok();
+		let tzdata_bearer_token = var("TZDATA_BEARER_TOKEN").or(file.tzdata_bearer_token);
+		let zic_path = var("ZIC_PATH").or(file.zic_path);
+		let canonical_timezones = env_or(
+			&mut errors,
+			var,
+			"CANONICAL_TIMEZONES",
+			"true or false",
+			file.canonical_timezones,
+		).unwrap_or(false);
+		let google_tag_id = var("GOOGLE_TAG_ID").or(file.google_tag_id);
 		
-		Arc::new(Self {
+		let config = Self {
 			listen_addr,
-			data_dir,
+			data_dir: data_dir.unwrap_or_default(),
 			maxmind_account_id,
 			maxmind_license_key,
 			maxmind_bearer_token,
 			maxmind_editions,
+			maxmind_merge_editions,
 			maxmind_download_url,
+			maxmind_checksum_url,
+			maxmind_edition_configs,
 			auto_update,
 			auto_update_interval,
-			api_key,
-			recaptcha_site_key,
-			recaptcha_secret_key,
+			retry_initial_delay,
+			retry_max_delay,
+			archive_retention,
+			historical_readers,
+			api_keys,
+			rate_limits,
+			rate_limit_ipv6_prefix,
+			max_batch_size,
+			trusted_proxies,
+			proxy_protocol,
+			proxy_protocol_trusted,
+			client_ip_headers,
+			captcha_provider,
+			captcha_site_key,
+			captcha_secret_key,
+			captcha_min_score,
+			captcha_action,
+			captcha_hostnames,
+			captcha_cache_ttl,
+			captcha_cache_size,
+			captcha_session_ttl,
+			captcha_session_secret,
+			captcha_session_secure,
 			osm_tiles_url,
 			tzdata_auto_update_interval: Some(tzdata_auto_update_interval).filter(|i| *i > 0),
 			tzdata_download_url,
 			tzdata_bearer_token,
 			zic_path,
+			canonical_timezones,
 			google_tag_id,
+		};
+		config.validate(&mut errors);
+		if !errors.is_empty() {
+			return Err(AppConfigError::Invalid(errors));
+		}
+		Ok(Arc::new(config))
+	}
+	
+	/// Whether `edition` should be updated automatically, either
+	/// because of global MaxMind settings or its own overrides.
+	pub fn edition_auto_update(&self, edition: &str) -> bool {
+		self.auto_update || self.maxmind_edition_configs
+			.get(edition)
+			.is_some_and(EditionConfig::enables_auto_update)
+	}
+	
+	/// Minimum score of tokens carrying one.
+	pub fn captcha_min_score(&self) -> f32 {
+		self.captcha_min_score.unwrap_or(DEFAULT_CAPTCHA_MIN_SCORE)
+	}
+	
+	/// Expected action of tokens carrying one, not checked if empty.
+	pub fn captcha_action(&self) -> &str {
+		self.captcha_action.as_deref().unwrap_or(DEFAULT_CAPTCHA_ACTION)
+	}
+	
+	/// Number of newest archives of `tag` (edition or tzdata) to keep, all if zero.
+	pub fn archive_retention(&self, tag: &str) -> usize {
+		self.maxmind_edition_configs.get(tag)
+			.and_then(|c| c.retention)
+			.unwrap_or(self.archive_retention)
+	}
+	
+	/// Whether forwarded headers sent by `ip` can be trusted.
+	pub fn is_trusted_proxy(&self, ip: IpAddr) -> bool {
+		let ip = ip.to_canonical();
+		self.trusted_proxies.iter().any(|network| network.contains(ip))
+	}
+	
+	/// Finds API key matching `token`, comparing in constant time.
+	pub fn find_api_key(&self, token: &str) -> Option<&ApiKey> {
+		self.api_keys.iter()
+			.find(|key| constant_time_eq(key.key.as_bytes(), token.as_bytes()))
+	}
+	
+	fn read_file<T: DeserializeOwned>(path: &Path) -> Result<T, AppConfigError> {
+		let content = std::fs::read_to_string(path)
+			.map_err(|err| AppConfigError::Io(path.to_path_buf(), err))?;
+		let is_yaml = matches!(
+			path.extension().and_then(|ext| ext.to_str()),
+			Some("yaml" | "yml"),
+		);
+		let res = if is_yaml {
+			serde_yaml::from_str(&content).map_err(|err| err.to_string())
+		} else {
+			toml::from_str(&content).map_err(|err| err.to_string())
+		};
+		res.map_err(|err| AppConfigError::Parse(path.to_path_buf(), err))
+	}
+	
+	fn validate(&self, errors: &mut Vec<String>) {
+		if self.maxmind_editions.is_empty() {
+			errors.push("MAXMIND_EDITIONS must not be empty".to_owned());
+		}
+		for edition in &self.maxmind_editions {
+			if !EDITION_PATTERN.is_match(edition) {
+				errors.push(format!(
+					"MAXMIND_EDITIONS contains invalid edition name \"{edition}\"",
+				));
+			}
+		}
+		for edition in &self.maxmind_merge_editions {
+			if !self.maxmind_editions.contains(edition) {
+				errors.push(format!(
+					"MAXMIND_MERGE_EDITIONS contains unknown edition \"{edition}\"",
+				));
+			}
+		}
+		let mut edition_configs: Vec<_> = self.maxmind_edition_configs.iter().collect();
+		edition_configs.sort_by_key(|(edition, _)| *edition);
+		for (edition, config) in edition_configs {
+			if !self.maxmind_editions.contains(edition) {
+				errors.push(format!("Settings specified for unknown edition \"{edition}\""));
+			}
+			if config.auto_update_interval == Some(0) {
+				errors.push(format!("auto_update_interval of edition \"{edition}\" must be positive"));
+			}
+			if config.licence_key.is_some() && config.account_id.is_none() {
+				errors.push(format!("licence_key of edition \"{edition}\" requires account_id"));
+			}
+			if config.auth == Some(EditionAuth::Bearer)
+				&& config.bearer_token.is_none()
+				&& self.maxmind_bearer_token.is_none()
+			{
+				errors.push(format!("Bearer auth of edition \"{edition}\" requires bearer_token"));
+			}
+			if config.auth == Some(EditionAuth::Basic)
+				&& config.account_id.is_none()
+				&& self.maxmind_account_id.is_none()
+			{
+				errors.push(format!("Basic auth of edition \"{edition}\" requires account_id"));
+			}
+		}
+		for (i, key) in self.api_keys.iter().enumerate() {
+			if self.api_keys[..i].iter().any(|other| other.name == key.name) {
+				errors.push(format!("API key \"{}\" is defined more than once", key.name));
+			}
+			if key.key.is_empty() {
+				errors.push(format!("API key \"{}\" must not be empty", key.name));
+			}
+			if self.api_keys[..i].iter().any(|other| other.key == key.key) {
+				errors.push(format!("API key \"{}\" reuses key of another API key", key.name));
+			}
+		}
+		if self.auto_update_interval == 0 {
+			errors.push("AUTO_UPDATE_INTERVAL must be positive".to_owned());
+		}
+		if self.retry_initial_delay == 0 {
+			errors.push("RETRY_INITIAL_DELAY must be positive".to_owned());
+		}
+		if self.retry_max_delay < self.retry_initial_delay {
+			errors.push("RETRY_MAX_DELAY must not be less than RETRY_INITIAL_DELAY".to_owned());
+		}
+		if self.proxy_protocol && self.proxy_protocol_trusted.is_empty() {
+			errors.push(
+				"PROXY_PROTOCOL requires PROXY_PROTOCOL_TRUSTED listing load balancers".to_owned(),
+			);
+		}
+		if self.rate_limit_ipv6_prefix > 128 {
+			errors.push("RATE_LIMIT_IPV6_PREFIX must be between 0 and 128".to_owned());
+		}
+		if self.max_batch_size == 0 {
+			errors.push("MAX_BATCH_SIZE must be positive".to_owned());
+		}
+		if self.maxmind_license_key.is_some() && self.maxmind_account_id.is_none() {
+			errors.push("MAXMIND_LICENCE_KEY requires MAXMIND_ACCOUNT_ID".to_owned());
+		}
+		if !(0.0..=1.0).contains(&self.captcha_min_score()) {
+			errors.push("CAPTCHA_MIN_SCORE must be between 0.0 and 1.0".to_owned());
+		}
+		if self.captcha_site_key.is_some() != self.captcha_secret_key.is_some() {
+			errors.push(
+				"CAPTCHA_SITE_KEY and CAPTCHA_SECRET_KEY must be set together".to_owned(),
+			);
+		}
+		if self.captcha_session_secret.as_ref().is_some_and(|secret| secret.len() < 32) {
+			errors.push("CAPTCHA_SESSION_SECRET must be at least 32 characters long".to_owned());
+		}
+	}
+}
+
+impl EditionConfig {
+	pub fn enables_auto_update(&self) -> bool {
+		self.download_url.is_some() || self.account_id.is_some() || self.bearer_token.is_some()
+	}
+}
+
+/// Overrides edition settings with `MAXMIND_{EDITION}_*` environment variables,
+/// where `{EDITION}` is upper-cased edition name with dashes replaced by underscores
+/// (e.g. `MAXMIND_GEOLITE2_CITY_DOWNLOAD_URL`).
+fn edition_config_from_env(
+	errors: &mut Vec<String>,
+	var: &dyn Fn(&str) -> Option<String>,
+	edition: &str,
+	config: EditionConfig,
+) -> EditionConfig {
+	let prefix = format!("MAXMIND_{}_", edition.to_uppercase().replace('-', "_"));
+	let edition_var = |name: &str| var(&format!("{prefix}{name}"));
+	EditionConfig {
+		download_url: edition_var("DOWNLOAD_URL").or(config.download_url),
+		checksum_url: edition_var("CHECKSUM_URL").or(config.checksum_url),
+		auth: env_or(errors, var, &format!("{prefix}AUTH"), "none, bearer or basic", config.auth),
+		account_id: edition_var("ACCOUNT_ID").or(config.account_id),
+		licence_key: edition_var("LICENCE_KEY").or(config.licence_key),
+		bearer_token: edition_var("BEARER_TOKEN").or(config.bearer_token),
+		auto_update_interval: env_or(
+			errors,
+			var,
+			&format!("{prefix}AUTO_UPDATE_INTERVAL"),
+			"a valid integer",
+			config.auto_update_interval,
+		),
+		retention: env_or(
+			errors,
+			var,
+			&format!("{prefix}RETENTION"),
+			"a valid integer",
+			config.retention,
+		),
+	}
+}
+
+/// Parses every item of list setting `name`, parse failures
+/// are recorded into `errors`.
+fn parse_items<T>(errors: &mut Vec<String>, name: &str, expected: &str, items: Vec<String>) -> Vec<T>
+where
+	T: FromStr,
+	T::Err: Display,
+{
+	items.into_iter()
+		.filter_map(|item| match item.parse() {
+			Ok(value) => Some(value),
+			Err(err) => {
+				errors.push(format!("{name} contains invalid {expected} \"{item}\": {err}"));
+				None
+			}
 		})
+		.collect()
+}
+
+/// Parses variable `name` returned by `var` falling back to `file_value`,
+/// parse failures are recorded into `errors`.
+fn env_or<T>(
+	errors: &mut Vec<String>,
+	var: &dyn Fn(&str) -> Option<String>,
+	name: &str,
+	expected: &str,
+	file_value: Option<T>,
+) -> Option<T>
+where
+	T: FromStr,
+	T::Err: Display,
+{
+	let Some(value) = var(name) else { return file_value };
+	match value.trim().parse() {
+		Ok(value) => Some(value),
+		Err(err) => {
+			errors.push(format!("{name} must be {expected}: {err}"));
+			None
+		}
 	}
 }
+
+pub fn parse_list(value: &str) -> Vec<String> {
+	value.split(',')
+		.map(str::trim)
+		.filter(|s| !s.is_empty())
+		.map(str::to_owned)
+		.collect()
+}
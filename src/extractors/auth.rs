@@ -1,74 +1,229 @@
 use std::marker::PhantomData;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use axum::extract::FromRequestParts;
 use axum::http::request::Parts;
-use axum::http::StatusCode;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum_extra::headers::Authorization;
-use axum_extra::headers::authorization::Bearer;
-use axum_extra::TypedHeader;
-use constant_time_eq::constant_time_eq;
+use axum_extra::headers::authorization::{Bearer, Credentials};
+use axum_extra::headers::Header;
 use log::error;
-use crate::state::AppState;
+use metrics::counter;
+use crate::config::{ApiKey, ApiScope, AppConfig};
+use crate::extractors::ClientIp;
+use crate::state::{AppState, RateLimitStatus, RateLimitSubject};
 
 pub trait AuthMode {
-	const ACCEPT_API_KEY: bool;
-	const ACCEPT_RECAPTCHA_TOKEN: bool;
+	/// Scope API key must have to pass.
+	const SCOPE: ApiScope;
+	const ACCEPT_RECAPTCHA_TOKEN: bool = false;
+	/// Reject requests when no API key is configured
+	/// instead of letting everyone in.
+	const REQUIRE_API_KEY: bool = false;
+	/// Leave rate limit check to handler, which calls
+	/// [`Auth::check_rate_limit`] once cost of request is known.
+	const DEFERRED_RATE_LIMIT: bool = false;
 }
 
 pub struct ApiKeyOrRecaptchaAuthMode;
 
 impl AuthMode for ApiKeyOrRecaptchaAuthMode {
-	const ACCEPT_API_KEY: bool = true;
+	const SCOPE: ApiScope = ApiScope::GeoIpLookup;
 	const ACCEPT_RECAPTCHA_TOKEN: bool = true;
 }
 
-pub struct ApiKeyAuthMode;
+pub struct ApiKeyOrRecaptchaBatchAuthMode;
 
-impl AuthMode for ApiKeyAuthMode {
-	const ACCEPT_API_KEY: bool = true;
-	const ACCEPT_RECAPTCHA_TOKEN: bool = false;
+impl AuthMode for ApiKeyOrRecaptchaBatchAuthMode {
+	const SCOPE: ApiScope = ApiScope::GeoIpLookup;
+	const ACCEPT_RECAPTCHA_TOKEN: bool = true;
+	const DEFERRED_RATE_LIMIT: bool = true;
+}
+
+pub struct MmdbFilesAuthMode;
+
+impl AuthMode for MmdbFilesAuthMode {
+	const SCOPE: ApiScope = ApiScope::FilesMmdb;
+}
+
+pub struct TzdataFilesAuthMode;
+
+impl AuthMode for TzdataFilesAuthMode {
+	const SCOPE: ApiScope = ApiScope::FilesTzdata;
+}
+
+pub struct TimezonesAuthMode;
+
+impl AuthMode for TimezonesAuthMode {
+	const SCOPE: ApiScope = ApiScope::Timezones;
+}
+
+pub struct AdminAuthMode;
+
+impl AuthMode for AdminAuthMode {
+	const SCOPE: ApiScope = ApiScope::Admin;
+	const REQUIRE_API_KEY: bool = true;
 }
 
 pub struct Auth<Mode: AuthMode> {
-	_phantom: PhantomData<Mode>,
+	/// API key having scope of `Mode`.
+	key: Option<ApiKey>,
+	subject: RateLimitSubject,
+	rate_limit_slot: Option<RateLimitSlot>,
+	_phantom: PhantomData<fn() -> Mode>,
 }
 
+/// Captcha session cookie issued while authenticating request,
+/// set by [`Auth`] and sent back by middleware which inserted it.
+#[derive(Debug, Clone, Default)]
+pub struct CaptchaSessionSlot(pub Arc<OnceLock<HeaderValue>>);
+
+/// Rate limit status of request, set by [`Auth`] and turned into
+/// `RateLimit-*` headers by middleware which inserted it.
+#[derive(Debug, Clone, Default)]
+pub struct RateLimitSlot(pub Arc<OnceLock<RateLimitStatus>>);
+
 pub type ApiKeyOrRecaptchaAuth = Auth<ApiKeyOrRecaptchaAuthMode>;
-pub type ApiKeyAuth = Auth<ApiKeyAuthMode>;
+pub type ApiKeyOrRecaptchaBatchAuth = Auth<ApiKeyOrRecaptchaBatchAuthMode>;
+pub type MmdbFilesAuth = Auth<MmdbFilesAuthMode>;
+pub type TzdataFilesAuth = Auth<TzdataFilesAuthMode>;
+pub type TimezonesAuth = Auth<TimezonesAuthMode>;
+pub type AdminAuth = Auth<AdminAuthMode>;
+
+/// API key request was made with, looked up once by `track_api_key`
+/// middleware and stored in request extensions.
+#[derive(Debug, Clone)]
+pub struct RequestApiKey(pub Option<ApiKey>);
+
+impl RequestApiKey {
+	pub fn name(&self) -> &str {
+		self.0.as_ref().map_or("anonymous", |key| &key.name)
+	}
+}
+
+/// Finds configured API key passed as bearer token.
+pub fn find_api_key<'a>(config: &'a AppConfig, headers: &HeaderMap) -> Option<&'a ApiKey> {
+	let value = headers.get(Authorization::<Bearer>::name())?;
+	let bearer = Bearer::decode(value)?;
+	config.find_api_key(bearer.token())
+}
 
 impl<Mode: AuthMode> FromRequestParts<Arc<AppState>> for Auth<Mode> {
-	type Rejection = StatusCode;
+	type Rejection = Response;
 	
 	async fn from_request_parts(
 		parts: &mut Parts,
 		state: &Arc<AppState>,
 	) -> Result<Self, Self::Rejection> {
-		if Mode::ACCEPT_API_KEY {
-			let Some(expected_token) = &state.config.api_key else {
-				return Ok(Self { _phantom: PhantomData });
-			};
-			let auth = TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, state)
-				.await
-				.ok();
-			if let Some(auth) = auth {
-				if constant_time_eq(auth.token().as_bytes(), expected_token.as_bytes()) {
-					return Ok(Self { _phantom: PhantomData });
-				}
+		let api_key = parts.extensions.get::<RequestApiKey>().and_then(|key| key.0.clone());
+		// Keys lacking scope are limited same way as anonymous requests
+		let key = api_key.clone().filter(|key| key.scopes.contains(&Mode::SCOPE));
+		let ClientIp(client_ip) = ClientIp::from_request_parts(parts, state)
+			.await
+			.map_err(IntoResponse::into_response)?;
+		let subject = match &key {
+			Some(key) => RateLimitSubject::Key(key.name.clone()),
+			None => RateLimitSubject::Ip(client_ip),
+		};
+		let auth = Self {
+			key,
+			subject,
+			rate_limit_slot: parts.extensions.get::<RateLimitSlot>().cloned(),
+			_phantom: PhantomData,
+		};
+		if !Mode::DEFERRED_RATE_LIMIT {
+			auth.check_rate_limit(state, 1).map_err(IntoResponse::into_response)?;
+		}
+		if state.config.api_keys.is_empty() {
+			if Mode::REQUIRE_API_KEY {
+				return Err(StatusCode::UNAUTHORIZED.into_response());
 			}
+			return Ok(auth);
+		}
+		let mut status = StatusCode::UNAUTHORIZED;
+		if let Some(api_key) = api_key {
+			if api_key.scopes.contains(&Mode::SCOPE) {
+				return Ok(auth);
+			}
+			status = StatusCode::FORBIDDEN;
 		}
 		if Mode::ACCEPT_RECAPTCHA_TOKEN {
-			let token = parts.headers.get("x-recaptcha-token")
+			if state.recaptcha.verify_session(&parts.headers, client_ip) {
+				return Ok(auth);
+			}
+			// X-Recaptcha-Token is kept for compatibility
+			let token = parts.headers.get("x-captcha-token")
+				.or_else(|| parts.headers.get("x-recaptcha-token"))
 				.and_then(|v| v.to_str().ok());
 			if let Some(token) = token {
-				match state.recaptcha.verify(token).await {
-					Ok(true) => return Ok(Self { _phantom: PhantomData }),
+				match state.recaptcha.verify(token, Some(client_ip)).await {
+					Ok(true) => {
+						if let Some(cookie) = state.recaptcha.issue_session(client_ip)
+							&& let Some(slot) = parts.extensions.get::<CaptchaSessionSlot>()
+						{
+							let _ = slot.0.set(cookie);
+						}
+						return Ok(auth);
+					},
 					Ok(false) => {}
 					Err(err) => {
-						error!("Unable to verify recaptcha token: {err}");
+						error!("Unable to verify captcha token: {err}");
 					},
 				}
 			}
 		}
-		Err(StatusCode::UNAUTHORIZED)
+		Err(status.into_response())
+	}
+}
+
+impl<Mode: AuthMode> Auth<Mode> {
+	/// Consumes `cost` tokens from rate limit bucket of request,
+	/// rejects request with `429` if bucket can't cover it.
+	pub fn check_rate_limit(&self, state: &AppState, cost: u32) -> Result<(), RateLimited> {
+		let Some(status) = state.rate_limits.check(
+			Mode::SCOPE,
+			self.key.as_ref(),
+			self.subject.clone(),
+			cost,
+		) else {
+			return Ok(());
+		};
+		if let Some(slot) = &self.rate_limit_slot {
+			let _ = slot.0.set(status);
+		}
+		if status.retry_after.is_some() {
+			counter!(
+				"rate_limited_requests_total",
+				"group" => Mode::SCOPE.name(),
+				"key" => self.key.as_ref().map_or("anonymous", |key| &key.name).to_owned(),
+			).increment(1);
+			return Err(RateLimited(status));
+		}
+		Ok(())
+	}
+}
+
+/// Rejection of request exceeding rate limit.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimited(RateLimitStatus);
+
+impl IntoResponse for RateLimited {
+	fn into_response(self) -> Response {
+		let retry_after = self.0.retry_after.unwrap_or_default().as_secs_f64().ceil() as u64;
+		(
+			StatusCode::TOO_MANY_REQUESTS,
+			[(header::RETRY_AFTER, HeaderValue::from(retry_after))],
+			rate_limit_headers(self.0),
+		).into_response()
 	}
 }
+
+/// `RateLimit-*` headers describing bucket state.
+pub fn rate_limit_headers(status: RateLimitStatus) -> [(header::HeaderName, HeaderValue); 3] {
+	let reset = status.reset.as_secs_f64().ceil() as u64;
+	[
+		(header::HeaderName::from_static("ratelimit-limit"), HeaderValue::from(status.limit)),
+		(header::HeaderName::from_static("ratelimit-remaining"), HeaderValue::from(status.remaining)),
+		(header::HeaderName::from_static("ratelimit-reset"), HeaderValue::from(reset)),
+	]
+}
@@ -1,34 +1,249 @@
 use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
 use axum::extract::{ConnectInfo, FromRequestParts};
 use axum::extract::connect_info::MockConnectInfo;
 use axum::http::request::Parts;
-use axum::http::StatusCode;
+use axum::http::{HeaderMap, StatusCode};
+use crate::config::{AppConfig, ClientIpHeader};
+use crate::proxy_protocol::ProxiedAddr;
+use crate::state::AppState;
 
 #[derive(Debug, Clone, Copy)]
 pub struct ClientIp(pub IpAddr);
 
-impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+impl FromRequestParts<Arc<AppState>> for ClientIp {
 	type Rejection = StatusCode;
 	
-	async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-		let mut ip = parts.headers.get("cf-connecting-ip")
-			.and_then(|v| v.to_str().ok())
-			.and_then(|v| v.parse::<IpAddr>().ok());
-		if ip.is_none() {
-			ip = parts.headers.get("x-forwarded-for")
-				.and_then(|v| v.to_str().ok())
-				.and_then(|v| v.split(',').next())
-				.and_then(|v| v.trim().parse::<IpAddr>().ok());
-		}
-		if ip.is_none() {
-			ip = parts.extensions.get::<ConnectInfo<SocketAddr>>()
-				.map(|v| v.ip());
+	async fn from_request_parts(
+		parts: &mut Parts,
+		state: &Arc<AppState>,
+	) -> Result<Self, Self::Rejection> {
+		let mut peer_ip = parts.extensions.get::<ConnectInfo<SocketAddr>>()
+			.map(|v| v.ip());
+		if peer_ip.is_none() {
+			peer_ip = parts.extensions.get::<ConnectInfo<ProxiedAddr>>()
+				.map(|v| v.0.0.ip());
 		}
-		if ip.is_none() {
-			ip = parts.extensions.get::<MockConnectInfo<SocketAddr>>()
+		if peer_ip.is_none() {
+			peer_ip = parts.extensions.get::<MockConnectInfo<SocketAddr>>()
 				.map(|v| v.0.ip());
 		}
-		let ip = ip.ok_or(StatusCode::BAD_REQUEST)?;
+		let peer_ip = peer_ip.ok_or(StatusCode::BAD_REQUEST)?.to_canonical();
+		if !state.config.is_trusted_proxy(peer_ip) {
+			return Ok(Self(peer_ip));
+		}
+		let ip = state.config.client_ip_headers.iter()
+			.find_map(|header| Self::from_header(&state.config, &parts.headers, *header))
+			.unwrap_or(peer_ip);
 		Ok(Self(ip))
 	}
 }
+
+impl ClientIp {
+	fn from_header(
+		config: &AppConfig,
+		headers: &HeaderMap,
+		header: ClientIpHeader,
+	) -> Option<IpAddr> {
+		match header {
+			ClientIpHeader::XForwardedFor => {
+				let hops = headers.get_all(header.name())
+					.iter()
+					.map(|v| v.to_str().ok())
+					.collect::<Option<Vec<_>>>()?;
+				let hops = hops.iter()
+					.flat_map(|v| v.split(','))
+					.map(|v| v.trim().parse::<IpAddr>().ok().map(|ip| ip.to_canonical()));
+				Self::first_untrusted_hop(config, hops)
+			},
+			ClientIpHeader::Forwarded => {
+				let elements = headers.get_all(header.name())
+					.iter()
+					.map(|v| v.to_str().ok())
+					.collect::<Option<Vec<_>>>()?;
+				let hops = elements.iter()
+					.flat_map(|v| split_unquoted(v, ','))
+					.map(Self::parse_forwarded_element)
+					.collect::<Vec<_>>();
+				Self::first_untrusted_hop(config, hops.into_iter())
+			},
+			_ => headers.get(header.name())
+				.and_then(|v| v.to_str().ok())
+				.and_then(|v| v.trim().parse::<IpAddr>().ok())
+				.map(|ip| ip.to_canonical()),
+		}
+	}
+	
+	/// Extracts address from `for` parameter of RFC 7239 `Forwarded` element
+	/// (e.g. `for="[2001:db8::1]:4711";proto=https`). Obfuscated identifiers,
+	/// `unknown` and missing `for` yield `None`.
+	fn parse_forwarded_element(element: &str) -> Option<IpAddr> {
+		let node = split_unquoted(element, ';')
+			.into_iter()
+			.filter_map(|pair| pair.split_once('='))
+			.find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+			.map(|(_, value)| unquote(value.trim()))?;
+		let ip = node.parse::<IpAddr>().ok()
+			.or_else(|| node.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+			.or_else(|| node.strip_prefix('[')?.strip_suffix(']')?.parse().ok())?;
+		Some(ip.to_canonical())
+	}
+	
+	/// Walks proxy chain (ordered from client to nearest proxy) right-to-left
+	/// skipping trusted proxies. Unusable hop (unparsable, obfuscated, `unknown`
+	/// or without `for`) stops the walk at the last usable address, i.e. the
+	/// trusted proxy which reported it, since hops to the left of it can't
+	/// be verified. `None` if the nearest hop is unusable already.
+	fn first_untrusted_hop(
+		config: &AppConfig,
+		hops: impl DoubleEndedIterator<Item = Option<IpAddr>>,
+	) -> Option<IpAddr> {
+		let mut last = None;
+		for hop in hops.rev() {
+			let Some(ip) = hop else { break };
+			if !config.is_trusted_proxy(ip) {
+				return Some(ip);
+			}
+			last = Some(ip);
+		}
+		last
+	}
+}
+
+/// Splits `value` by `sep` ignoring separators inside quoted strings.
+fn split_unquoted(value: &str, sep: char) -> Vec<&str> {
+	let mut out = Vec::new();
+	let mut start = 0;
+	let mut quoted = false;
+	let mut escaped = false;
+	for (i, c) in value.char_indices() {
+		match c {
+			_ if escaped => escaped = false,
+			'\\' if quoted => escaped = true,
+			'"' => quoted = !quoted,
+			c if c == sep && !quoted => {
+				out.push(value[start..i].trim());
+				start = i + 1;
+			},
+			_ => {}
+		}
+	}
+	out.push(value[start..].trim());
+	out
+}
+
+fn unquote(value: &str) -> String {
+	let Some(value) = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) else {
+		return value.to_owned();
+	};
+	let mut out = String::with_capacity(value.len());
+	let mut chars = value.chars();
+	while let Some(c) = chars.next() {
+		match c {
+			'\\' => out.extend(chars.next()),
+			c => out.push(c),
+		}
+	}
+	out
+}
+
+#[cfg(test)]
+mod tests {
+	use axum::http::HeaderValue;
+	use super::*;
+	
+	fn config() -> Arc<AppConfig> {
+		AppConfig::from_toml(r#"
+			data_dir = "/nonexistent"
+			trusted_proxies = ["10.0.0.0/8", "fd00::/8"]
+		"#).expect("Invalid config")
+	}
+	
+	fn ip(ip: &str) -> Option<IpAddr> {
+		Some(ip.parse().expect("Invalid address"))
+	}
+	
+	fn from_headers(header: ClientIpHeader, values: &[&str]) -> Option<IpAddr> {
+		let mut headers = HeaderMap::new();
+		for value in values {
+			headers.append(header.name(), HeaderValue::from_str(value).expect("Invalid header"));
+		}
+		ClientIp::from_header(&config(), &headers, header)
+	}
+	
+	#[test]
+	fn parses_forwarded_element() {
+		let parse = ClientIp::parse_forwarded_element;
+		assert_eq!(parse("for=192.0.2.60;proto=http;by=203.0.113.43"), ip("192.0.2.60"));
+		assert_eq!(parse("proto=https; For=\"192.0.2.60:8080\""), ip("192.0.2.60"));
+		assert_eq!(parse("for=\"[2001:db8:cafe::17]:4711\""), ip("2001:db8:cafe::17"));
+		assert_eq!(parse("for=\"[2001:db8:cafe::17]\""), ip("2001:db8:cafe::17"));
+		assert_eq!(parse("for=\"2001:db8:cafe::17\""), ip("2001:db8:cafe::17"));
+		assert_eq!(parse("for=\"[::ffff:192.0.2.60]\""), ip("192.0.2.60"));
+	}
+	
+	#[test]
+	fn ignores_unusable_forwarded_element() {
+		let parse = ClientIp::parse_forwarded_element;
+		assert_eq!(parse("for=unknown"), None);
+		assert_eq!(parse("for=_hidden"), None);
+		assert_eq!(parse("for=\"_SEVKISEK:4711\""), None);
+		assert_eq!(parse("proto=https;by=203.0.113.43"), None);
+		assert_eq!(parse("for="), None);
+		assert_eq!(parse("for=\"[2001:db8:cafe::17\""), None);
+		assert_eq!(parse("for=192.0.2.60:port"), None);
+		assert_eq!(parse(""), None);
+	}
+	
+	#[test]
+	fn splits_outside_quotes() {
+		assert_eq!(
+			split_unquoted("for=\"a,b\";x=\"c\\\",d\", for=e", ','),
+			["for=\"a,b\";x=\"c\\\",d\"", "for=e"],
+		);
+		assert_eq!(split_unquoted("for=a; proto=\"x;y\"", ';'), ["for=a", "proto=\"x;y\""]);
+		assert_eq!(unquote("\"a\\\"b\\\\c\""), "a\"b\\c");
+		assert_eq!(unquote("\"unterminated"), "\"unterminated");
+	}
+	
+	#[test]
+	fn skips_trusted_forwarded_hops() {
+		let header = ClientIpHeader::Forwarded;
+		assert_eq!(
+			from_headers(header, &["for=192.0.2.1, for=198.51.100.1;proto=https, for=10.0.0.1"]),
+			ip("198.51.100.1"),
+		);
+		assert_eq!(
+			from_headers(header, &["for=192.0.2.1", "for=\"[2001:db8::1]:4711\"", "for=\"[fd00::1]\""]),
+			ip("2001:db8::1"),
+		);
+		// Only trusted proxies, leftmost one is the best guess
+		assert_eq!(from_headers(header, &["for=10.0.0.2, for=10.0.0.1"]), ip("10.0.0.2"));
+		// Obfuscated hop hides the real client, walk stops at proxy which reported it
+		assert_eq!(
+			from_headers(header, &["for=192.0.2.1, for=_hidden, for=10.0.0.1"]),
+			ip("10.0.0.1"),
+		);
+		assert_eq!(
+			from_headers(header, &["for=192.0.2.1, for=unknown, for=198.51.100.1, for=10.0.0.1"]),
+			ip("198.51.100.1"),
+		);
+		assert_eq!(
+			from_headers(header, &["for=192.0.2.1, proto=https, for=10.0.0.1"]),
+			ip("10.0.0.1"),
+		);
+		// Nearest hop is unusable, nothing to report
+		assert_eq!(from_headers(header, &["for=192.0.2.1, for=\"_a,b\";proto=http"]), None);
+	}
+	
+	#[test]
+	fn skips_trusted_x_forwarded_for_hops() {
+		let header = ClientIpHeader::XForwardedFor;
+		assert_eq!(from_headers(header, &["192.0.2.1, 198.51.100.1, 10.0.0.1"]), ip("198.51.100.1"));
+		assert_eq!(from_headers(header, &["192.0.2.1", "2001:db8::1, fd00::1"]), ip("2001:db8::1"));
+		assert_eq!(from_headers(header, &["::ffff:192.0.2.1"]), ip("192.0.2.1"));
+		assert_eq!(from_headers(header, &["192.0.2.1, garbage, 10.0.0.1"]), ip("10.0.0.1"));
+		assert_eq!(from_headers(header, &["192.0.2.1, garbage"]), None);
+		assert_eq!(from_headers(header, &[]), None);
+	}
+}
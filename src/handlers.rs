@@ -1,22 +1,31 @@
 use std::sync::Arc;
 use std::time::Instant;
-use ahash::AHashMap;
 use axum::extract::{Path, Query, State};
 use axum::{middleware, Json, Router};
 use axum::body::{Body, Bytes};
-use axum::http::{Request, Response, StatusCode};
+use axum::http::{header, Request, Response, StatusCode};
 use axum::middleware::Next;
 use axum::response::{Html, IntoResponse};
-use axum::routing::get;
-use axum_extra::headers::IfModifiedSince;
+use axum::routing::{get, post};
+use axum_extra::headers::{ETag, IfModifiedSince, IfNoneMatch};
 use axum_extra::TypedHeader;
-use log::error;
-use metrics::histogram;
+use log::{debug, error};
+use metrics::{counter, histogram};
 use tower_http::services::{ServeDir, ServeFile};
 use utoipa_swagger_ui::SwaggerUi;
-use crate::extractors::{ApiKeyAuth, ApiKeyOrRecaptchaAuth, ClientIp};
-use crate::model::{ErrorDTO, GeoIpLookupQuery, GeoIpLookupResult, GeoIpStatus, IndexPageCtx, IpDetectResult};
-use crate::state::{AppState, MaxMindServiceError};
+use crate::config::parse_list;
+use crate::extractors::{
+	find_api_key, rate_limit_headers, AdminAuth, ApiKeyOrRecaptchaAuth, ApiKeyOrRecaptchaBatchAuth,
+	CaptchaSessionSlot, ClientIp, MmdbFilesAuth, RateLimitSlot, RequestApiKey, TimezonesAuth,
+	TzdataFilesAuth,
+};
+use crate::model::{
+	EditionVersions, ErrorDTO, GeoIpBatchLookupError, GeoIpBatchLookupItem, GeoIpBatchLookupQuery,
+	GeoIpBatchLookupRequest, GeoIpLookupQuery, GeoIpLookupResult, GeoIpStatus, IndexPageCtx,
+	IpDetectResult, TimezoneDetails, TimezoneListQuery, TimezoneQuery, UpdateResult, UpdateStatus,
+	UpdateTriggerQuery, VersionPinQuery,
+};
+use crate::state::{AppState, MaxMindServiceError, VersionSelector, TZDATA_TAG};
 
 pub fn build_router(state: Arc<AppState>) -> Router {
 	let openapi_spec: serde_json::Value = serde_yaml::from_str(include_str!("../openapi.yaml"))
@@ -32,7 +41,15 @@ pub fn build_router(state: Arc<AppState>) -> Router {
 		.route("/api/status", get(get_status))
 		.route("/api/ip", get(detect_ip))
 		.route("/api/geoip", get(lookup_geoip))
+		.route("/api/geoip/batch", post(lookup_geoip_batch))
 		.route("/api/timezones", get(get_all_timezones))
+		.route("/api/timezones/{*zone}", get(get_timezone))
+		.route("/api/admin/update", get(get_update_status).post(trigger_update))
+		.route("/api/admin/versions/{edition}", get(get_edition_versions))
+		.route(
+			"/api/admin/versions/{edition}/pin",
+			post(pin_edition_version).delete(unpin_edition_version),
+		)
 		.route("/api/metrics", get(|| async move { metric_handle.render() }))
 		.merge(
 			SwaggerUi::new("/swagger-ui")
@@ -47,7 +64,10 @@ pub fn build_router(state: Arc<AppState>) -> Router {
 		.nest_service("/static", ServeDir::new("dist/static").precompressed_gzip())
 		.route("/files/tzdata", get(download_tzdata_archive_file))
 		.route("/files/mmdb/{edition}", get(download_mmdb_archive_file))
+		.layer(middleware::from_fn(issue_captcha_session))
+		.layer(middleware::from_fn(add_rate_limit_headers))
 		.layer(middleware::from_fn(log_internal_server_errors))
+		.layer(middleware::from_fn_with_state(state.clone(), track_api_key))
 		.layer(prometheus_layer)
 		.with_state(state)
 }
@@ -67,7 +87,7 @@ async fn get_status(State(state): State<Arc<AppState>>) -> Json<GeoIpStatus> {
 
 async fn download_tzdata_archive_file(
 	State(state): State<Arc<AppState>>,
-	_auth: ApiKeyAuth,
+	_auth: TzdataFilesAuth,
 	if_modified_since: Option<TypedHeader<IfModifiedSince>>,
 ) -> Result<axum::response::Response, ErrorDTO> {
 	let info = state.timezones.get_archive().ok_or_else(|| ErrorDTO::new_static(
@@ -84,11 +104,15 @@ async fn download_tzdata_archive_file(
 
 async fn download_mmdb_archive_file(
 	State(state): State<Arc<AppState>>,
-	_auth: ApiKeyAuth,
-	Path(edition): Path<String>,
+	_auth: MmdbFilesAuth,
+	Path(file_name): Path<String>,
 	if_modified_since: Option<TypedHeader<IfModifiedSince>>,
 ) -> Result<axum::response::Response, ErrorDTO> {
-	let info = match state.maxmind.get_archive(&edition) {
+	let (edition, checksum) = match file_name.strip_suffix(".sha256") {
+		Some(edition) => (edition, true),
+		None => (file_name.as_str(), false),
+	};
+	let info = match state.maxmind.get_archive(edition) {
 		Ok(info) => Ok(info),
 		Err(MaxMindServiceError::UnknownEdition | MaxMindServiceError::MissingDatabase) => {
 			Err(ErrorDTO::new_static(
@@ -98,11 +122,12 @@ async fn download_mmdb_archive_file(
 		},
 		Err(err) => Err(err.into()),
 	}?;
-	let res = state.files.download_archive(
-		info,
-		&format!("{}.tar.gz", edition),
-		if_modified_since,
-	).await?;
+	let archive_name = format!("{edition}.tar.gz");
+	let res = if checksum {
+		state.files.download_archive_checksum(info, &archive_name).await?
+	} else {
+		state.files.download_archive(info, &archive_name, if_modified_since).await?
+	};
 	Ok(res)
 }
 
@@ -121,40 +146,281 @@ async fn lookup_geoip(
 	let start = Instant::now();
 	let ip = query.ip.unwrap_or(client_ip);
 	let locale = query.locale.as_deref().unwrap_or("en");
-	let edition = query.edition.as_deref().or_else(|| state.maxmind.default_edition());
-	match state.maxmind.lookup(ip, locale, edition) {
-		Ok(info) => {
+	let requested = query.editions.as_deref().map(parse_list).unwrap_or_default();
+	let editions = state.maxmind.resolve_editions(
+		query.edition.as_deref(),
+		requested.iter().map(String::as_str).collect(),
+	).map_err(lookup_error_to_dto)?;
+	let selector = match (query.at, query.version) {
+		(None, None) => None,
+		(Some(at), None) => Some(VersionSelector::At(at)),
+		(None, Some(version)) => Some(VersionSelector::Exact(version)),
+		(Some(_), Some(_)) => return Err(ErrorDTO::new_static(
+			StatusCode::BAD_REQUEST,
+			"at and version are mutually exclusive",
+		)),
+	};
+	let res = match selector {
+		Some(selector) => {
+			state.maxmind.lookup_historical(ip, locale, &editions, selector, query.timezone_details).await
+		},
+		None => state.maxmind.lookup(ip, locale, &editions, query.timezone_details),
+	};
+	match res {
+		Ok(res) => {
 			let elapsed = start.elapsed();
 			histogram!(
 				"lookup_duration_seconds",
-				"edition" => edition.unwrap_or("Unknown").to_owned(),
+				"edition" => editions_label(&editions),
+				"mode" => "single",
 			).record(elapsed.as_secs_f64());
 			Ok(Json(GeoIpLookupResult {
 				ip,
-				info,
+				network: res.network,
+				info: res.info,
+				versions: res.versions,
 				elapsed: elapsed.as_secs_f64(),
 			}))
 		},
-		Err(MaxMindServiceError::UnknownEdition) => Err(ErrorDTO::new_static(
+		Err(err) => Err(lookup_error_to_dto(err)),
+	}
+}
+
+async fn lookup_geoip_batch(
+	State(state): State<Arc<AppState>>,
+	auth: ApiKeyOrRecaptchaBatchAuth,
+	Query(query): Query<GeoIpBatchLookupQuery>,
+	Json(req): Json<GeoIpBatchLookupRequest>,
+) -> Result<Json<Vec<GeoIpBatchLookupItem>>, Response<Body>> {
+	if req.ips.len() > state.config.max_batch_size {
+		return Err(ErrorDTO::new(
+			StatusCode::PAYLOAD_TOO_LARGE,
+			format!("Batch size must not exceed {} addresses", state.config.max_batch_size),
+		).into_response());
+	}
+	// Every address costs as much as single lookup
+	auth.check_rate_limit(&state, req.ips.len().max(1) as u32)
+		.map_err(IntoResponse::into_response)?;
+	let locale = req.locale.as_deref().unwrap_or("en");
+	let editions = state.maxmind.resolve_editions(
+		req.edition.as_deref(),
+		req.editions.iter().map(String::as_str).collect(),
+	).map_err(|err| lookup_error_to_dto(err).into_response())?;
+	state.maxmind.check_editions(&editions)
+		.map_err(|err| lookup_error_to_dto(err).into_response())?;
+	let edition_label = editions_label(&editions);
+	let mut items = Vec::with_capacity(req.ips.len());
+	for ip in &req.ips {
+		let item_start = Instant::now();
+		let Ok(parsed_ip) = ip.trim().parse() else {
+			items.push(GeoIpBatchLookupItem::Err(GeoIpBatchLookupError {
+				ip: ip.clone(),
+				status: StatusCode::BAD_REQUEST.as_u16(),
+				error: "Invalid IP address".to_owned(),
+			}));
+			continue;
+		};
+		match state.maxmind.lookup(parsed_ip, locale, &editions, query.timezone_details) {
+			Ok(res) => {
+				let elapsed = item_start.elapsed();
+				histogram!(
+					"lookup_duration_seconds",
+					"edition" => edition_label.clone(),
+					"mode" => "batch",
+				).record(elapsed.as_secs_f64());
+				items.push(GeoIpBatchLookupItem::Ok(Box::new(GeoIpLookupResult {
+					ip: parsed_ip,
+					network: res.network,
+					info: res.info,
+					versions: res.versions,
+					elapsed: elapsed.as_secs_f64(),
+				})));
+			},
+			Err(err) => {
+				let err = lookup_error_to_dto(err);
+				items.push(GeoIpBatchLookupItem::Err(GeoIpBatchLookupError {
+					ip: ip.clone(),
+					status: err.status,
+					error: err.error,
+				}));
+			},
+		}
+	}
+	Ok(Json(items))
+}
+
+/// Metric label of looked up editions, merged lookups share one label
+/// so that requested combinations don't multiply series.
+fn editions_label(editions: &[&str]) -> String {
+	match editions {
+		[] => "Unknown".to_owned(),
+		[edition] => (*edition).to_owned(),
+		_ => "merged".to_owned(),
+	}
+}
+
+fn lookup_error_to_dto(err: MaxMindServiceError) -> ErrorDTO {
+	match err {
+		MaxMindServiceError::UnknownEdition => ErrorDTO::new_static(
 			StatusCode::NOT_FOUND,
 			"Unknown MaxMind database edition",
-		)),
-		Err(MaxMindServiceError::MissingDatabase) => Err(ErrorDTO::new_static(
+		),
+		MaxMindServiceError::MissingDatabase => ErrorDTO::new_static(
 			StatusCode::SERVICE_UNAVAILABLE,
 			"Missing MaxMind database",
-		)),
-		Err(err) => Err(err.into()),
+		),
+		MaxMindServiceError::UnknownVersion => ErrorDTO::new_static(
+			StatusCode::NOT_FOUND,
+			"Unknown MaxMind database version",
+		),
+		err => err.into(),
 	}
 }
 
 async fn get_all_timezones(
 	State(state): State<Arc<AppState>>,
-	_auth: ApiKeyAuth,
-) -> Json<AHashMap<String, String>> {
-	Json(state.timezones.get_all())
+	_auth: TimezonesAuth,
+	Query(query): Query<TimezoneListQuery>,
+	if_none_match: Option<TypedHeader<IfNoneMatch>>,
+) -> Result<axum::response::Response, ErrorDTO> {
+	let prefixes = query.prefix.as_deref().map(parse_list).unwrap_or_default();
+	let prefixes: Vec<&str> = prefixes.iter().map(String::as_str).collect();
+	let export = state.timezones.export(&prefixes, query.format);
+	let etag: ETag = export.etag.parse().map_err(|_| ErrorDTO::new_static(
+		StatusCode::INTERNAL_SERVER_ERROR,
+		"Invalid ETag",
+	))?;
+	if let Some(TypedHeader(if_none_match)) = if_none_match
+		&& !if_none_match.precondition_passes(&etag)
+	{
+		return Ok((StatusCode::NOT_MODIFIED, TypedHeader(etag)).into_response());
+	}
+	Ok((
+		StatusCode::OK,
+		[(header::CONTENT_TYPE, export.content_type())],
+		TypedHeader(etag),
+		export.render(),
+	).into_response())
+}
+
+async fn get_timezone(
+	State(state): State<Arc<AppState>>,
+	_auth: TimezonesAuth,
+	Path(zone): Path<String>,
+	Query(query): Query<TimezoneQuery>,
+) -> Result<Json<TimezoneDetails>, ErrorDTO> {
+	let details = state.timezones.details(&zone, query.transitions).ok_or_else(|| {
+		ErrorDTO::new_static(StatusCode::NOT_FOUND, "Unknown timezone")
+	})?;
+	Ok(Json(details))
+}
+
+async fn get_update_status(
+	State(state): State<Arc<AppState>>,
+	_auth: AdminAuth,
+) -> Json<Vec<UpdateStatus>> {
+	let mut statuses = state.maxmind.update_statuses();
+	statuses.push(state.timezones.update_status());
+	Json(statuses)
+}
+
+async fn trigger_update(
+	State(state): State<Arc<AppState>>,
+	_auth: AdminAuth,
+	Query(query): Query<UpdateTriggerQuery>,
+) -> Result<Json<Vec<UpdateResult>>, ErrorDTO> {
+	let results = match query.target.as_deref() {
+		Some(TZDATA_TAG) => vec![state.timezones.force_update().await],
+		Some(edition) => vec![state.maxmind.force_update(edition).await.map_err(lookup_error_to_dto)?],
+		None => {
+			let mut results = state.maxmind.force_update_all().await;
+			results.push(state.timezones.force_update().await);
+			results
+		},
+	};
+	Ok(Json(results))
+}
+
+async fn get_edition_versions(
+	State(state): State<Arc<AppState>>,
+	_auth: AdminAuth,
+	Path(edition): Path<String>,
+) -> Result<Json<EditionVersions>, ErrorDTO> {
+	let versions = state.maxmind.list_versions(&edition).map_err(lookup_error_to_dto)?;
+	Ok(Json(versions))
+}
+
+async fn pin_edition_version(
+	State(state): State<Arc<AppState>>,
+	_auth: AdminAuth,
+	Path(edition): Path<String>,
+	Query(query): Query<VersionPinQuery>,
+) -> Result<Json<EditionVersions>, ErrorDTO> {
+	let versions = state.maxmind.pin_version(&edition, query.timestamp)
+		.await
+		.map_err(lookup_error_to_dto)?;
+	Ok(Json(versions))
+}
+
+async fn unpin_edition_version(
+	State(state): State<Arc<AppState>>,
+	_auth: AdminAuth,
+	Path(edition): Path<String>,
+) -> Result<Json<EditionVersions>, ErrorDTO> {
+	let versions = state.maxmind.unpin_version(&edition)
+		.await
+		.map_err(lookup_error_to_dto)?;
+	Ok(Json(versions))
+}
+
+async fn track_api_key(
+	State(state): State<Arc<AppState>>,
+	mut req: Request<Body>,
+	next: Next,
+) -> Response<Body> {
+	let key = RequestApiKey(find_api_key(&state.config, req.headers()).cloned());
+	let name = key.name().to_owned();
+	let method = req.method().clone();
+	let path = req.uri().path().to_owned();
+	req.extensions_mut().insert(key);
+	let res = next.run(req).await;
+	debug!("{method} {path} {} (key={name})", res.status().as_u16());
+	counter!(
+		"api_key_requests_total",
+		"key" => name,
+		"status" => res.status().as_u16().to_string(),
+	).increment(1);
+	res
+}
+
+async fn issue_captcha_session(mut req: Request<Body>, next: Next) -> Response<Body> {
+	let slot = CaptchaSessionSlot::default();
+	req.extensions_mut().insert(slot.clone());
+	let mut res = next.run(req).await;
+	if let Some(cookie) = slot.0.get()
+		&& res.status().is_success()
+	{
+		res.headers_mut().append(header::SET_COOKIE, cookie.clone());
+	}
+	res
+}
+
+async fn add_rate_limit_headers(mut req: Request<Body>, next: Next) -> Response<Body> {
+	let slot = RateLimitSlot::default();
+	req.extensions_mut().insert(slot.clone());
+	let mut res = next.run(req).await;
+	if let Some(status) = slot.0.get() {
+		for (name, value) in rate_limit_headers(*status) {
+			res.headers_mut().insert(name, value);
+		}
+	}
+	res
 }
 
 async fn log_internal_server_errors(req: Request<Body>, next: Next) -> Response<Body> {
+	let key = req.extensions().get::<RequestApiKey>()
+		.map_or("anonymous", RequestApiKey::name)
+		.to_owned();
 	let res = next.run(req).await;
 	if res.status() != StatusCode::INTERNAL_SERVER_ERROR {
 		return res;
@@ -167,8 +433,8 @@ async fn log_internal_server_errors(req: Request<Body>, next: Next) -> Response<
 			Bytes::default()
 		});
 	match serde_json::from_slice::<ErrorDTO>(&body_bytes) {
-		Ok(res) => error!("Internal Server Error: {}", res.error),
-		Err(_) => error!("Internal Server Error")
+		Ok(res) => error!("Internal Server Error (key={key}): {}", res.error),
+		Err(_) => error!("Internal Server Error (key={key})")
 	}
 	(StatusCode::INTERNAL_SERVER_ERROR, headers, body_bytes).into_response()
 }
@@ -1,21 +1,53 @@
 use std::net::SocketAddr;
-use log::info;
+use std::path::Path;
+use std::process::ExitCode;
+use clap::Parser;
+use log::{error, info, warn};
 use tokio::net::TcpListener;
+use crate::cli::{Cli, CliCommand};
 use crate::config::AppConfig;
 use crate::handlers::build_router;
+use crate::proxy_protocol::{ProxiedAddr, ProxyProtocolListener};
 use crate::state::AppState;
 
+mod cli;
 mod config;
 mod handlers;
+mod proxy_protocol;
+mod tzdata;
+mod tzif;
 mod state;
 mod model;
 mod extractors;
 
 #[tokio::main]
-async fn main() {
+async fn main() -> ExitCode {
 	let _ = dotenvy::dotenv();
-	env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
-	let config = AppConfig::load_from_env();
+	let cli = Cli::parse();
+	let command = cli.command.unwrap_or(CliCommand::Serve);
+	env_logger::init_from_env(
+		env_logger::Env::default().default_filter_or(command.default_log_level()),
+	);
+	match command {
+		CliCommand::Serve => serve(cli.config.as_deref()).await,
+		command => cli::run(command, cli.config.as_deref()).await,
+	}
+}
+
+async fn serve(config_file: Option<&Path>) -> ExitCode {
+	let config = match AppConfig::load(config_file) {
+		Ok(config) => config,
+		Err(err) => {
+			error!("{err}");
+			return ExitCode::FAILURE;
+		}
+	};
+	if !config.client_ip_headers.is_empty() && config.trusted_proxies.is_empty() {
+		warn!(
+			"CLIENT_IP_HEADERS are ignored because TRUSTED_PROXIES is empty, \
+			client address is taken from connection",
+		);
+	}
 	let state = AppState::new(config.clone()).await;
 	state.timezones.start_updater();
 	state.maxmind.start_updater();
@@ -24,7 +56,17 @@ async fn main() {
 		.await
 		.expect("Unable to bind TCP listener");
 	info!("Listening on http://{}/", listener.local_addr().expect("Unable to get local address"));
-	axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
-		.await
-		.expect("Unable to start Axum server");
+	if config.proxy_protocol {
+		info!("Expecting PROXY protocol header on every connection");
+		let listener = ProxyProtocolListener::new(listener, config.proxy_protocol_trusted.clone())
+			.expect("Unable to create PROXY protocol listener");
+		axum::serve(listener, router.into_make_service_with_connect_info::<ProxiedAddr>())
+			.await
+			.expect("Unable to start Axum server");
+	} else {
+		axum::serve(listener, router.into_make_service_with_connect_info::<SocketAddr>())
+			.await
+			.expect("Unable to start Axum server");
+	}
+	ExitCode::SUCCESS
 }
@@ -1,10 +1,14 @@
+use std::collections::BTreeMap;
 use std::net::IpAddr;
 use chrono::{DateTime, Utc};
+use ipnetwork::IpNetwork;
 use serde::{Deserialize, Serialize};
+use crate::model::TimezoneDatabaseStatus;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct GeoIpStatus {
 	pub databases: Vec<GeoIpDatabaseStatus>,
+	pub timezones: TimezoneDatabaseStatus,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -22,6 +26,26 @@ pub struct GeoIpDatabaseStatus {
 	pub last_update_check: Option<DateTime<Utc>>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub error: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub retry_attempts: Option<u32>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub next_retry: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GeoIpDatabaseMetadata {
+	pub database_type: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub timestamp: Option<DateTime<Utc>>,
+	pub binary_format_version: String,
+	pub ip_version: u16,
+	pub node_count: u32,
+	pub record_size: u16,
+	#[serde(skip_serializing_if = "Vec::is_empty")]
+	pub locales: Vec<String>,
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	pub description: BTreeMap<String, String>,
+	pub file_size: u64,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -34,13 +58,53 @@ pub struct GeoIpLookupQuery {
 	pub ip: Option<IpAddr>,
 	pub locale: Option<String>,
 	pub edition: Option<String>,
+	pub editions: Option<String>,
+	pub at: Option<DateTime<Utc>>,
+	pub version: Option<DateTime<Utc>>,
+	/// Whether to include current UTC offset and DST state of timezone.
+	#[serde(default)]
+	pub timezone_details: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GeoIpBatchLookupQuery {
+	/// Whether to include current UTC offset and DST state of timezone.
+	#[serde(default)]
+	pub timezone_details: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct GeoIpBatchLookupRequest {
+	pub ips: Vec<String>,
+	pub locale: Option<String>,
+	pub edition: Option<String>,
+	#[serde(default)]
+	pub editions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum GeoIpBatchLookupItem {
+	Ok(Box<GeoIpLookupResult>),
+	Err(GeoIpBatchLookupError),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GeoIpBatchLookupError {
+	pub ip: String,
+	pub status: u16,
+	pub error: String,
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub struct GeoIpLookupResult {
 	pub ip: IpAddr,
+	pub network: IpNetwork,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub info: Option<GeoIpInfo>,
+	/// Versions of databases result was looked up in, by edition.
+	#[serde(skip_serializing_if = "BTreeMap::is_empty")]
+	pub versions: BTreeMap<String, DateTime<Utc>>,
 	pub elapsed: f64,
 }
 
@@ -72,6 +136,13 @@ pub struct GeoIpInfo {
 	pub timezone: Option<String>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub posix_timezone: Option<String>,
+	/// Current offset of `timezone` in seconds east of UTC,
+	/// only if timezone details are requested.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub utc_offset: Option<i32>,
+	/// Whether daylight saving time is currently in effect in `timezone`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub dst: Option<bool>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub latitude: Option<f64>,
 	#[serde(skip_serializing_if = "Option::is_none")]
@@ -90,6 +161,80 @@ pub struct GeoIpInfo {
 	pub autonomous_system_number: Option<u32>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub autonomous_system_organization: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub is_anonymous: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub is_anonymous_vpn: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub is_hosting_provider: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub is_public_proxy: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub is_residential_proxy: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub is_tor_exit_node: Option<bool>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub connection_type: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub isp: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub organization: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub domain: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub mobile_country_code: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub mobile_network_code: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub user_type: Option<String>,
+}
+
+impl GeoIpInfo {
+	/// Fills fields missing in `self` with values from `other`,
+	/// fields already present in `self` take precedence.
+	pub fn merge(&mut self, other: GeoIpInfo) {
+		self.continent_id = self.continent_id.or(other.continent_id);
+		self.continent_code = self.continent_code.take().or(other.continent_code);
+		self.continent_name = self.continent_name.take().or(other.continent_name);
+		self.country_id = self.country_id.or(other.country_id);
+		self.country_iso_code = self.country_iso_code.take().or(other.country_iso_code);
+		self.country_name = self.country_name.take().or(other.country_name);
+		if self.subdivisions.is_empty() {
+			self.subdivisions = other.subdivisions;
+		}
+		self.city_id = self.city_id.or(other.city_id);
+		self.city_name = self.city_name.take().or(other.city_name);
+		self.metro_code = self.metro_code.or(other.metro_code);
+		self.postal_code = self.postal_code.take().or(other.postal_code);
+		self.timezone = self.timezone.take().or(other.timezone);
+		self.posix_timezone = self.posix_timezone.take().or(other.posix_timezone);
+		self.utc_offset = self.utc_offset.or(other.utc_offset);
+		self.dst = self.dst.or(other.dst);
+		self.latitude = self.latitude.or(other.latitude);
+		self.longitude = self.longitude.or(other.longitude);
+		self.accuracy_radius = self.accuracy_radius.or(other.accuracy_radius);
+		self.is_in_european_union = self.is_in_european_union.or(other.is_in_european_union);
+		self.is_anonymous_proxy = self.is_anonymous_proxy.or(other.is_anonymous_proxy);
+		self.is_anycast = self.is_anycast.or(other.is_anycast);
+		self.is_satellite_provider = self.is_satellite_provider.or(other.is_satellite_provider);
+		self.autonomous_system_number = self.autonomous_system_number
+			.or(other.autonomous_system_number);
+		self.autonomous_system_organization = self.autonomous_system_organization.take()
+			.or(other.autonomous_system_organization);
+		self.is_anonymous = self.is_anonymous.or(other.is_anonymous);
+		self.is_anonymous_vpn = self.is_anonymous_vpn.or(other.is_anonymous_vpn);
+		self.is_hosting_provider = self.is_hosting_provider.or(other.is_hosting_provider);
+		self.is_public_proxy = self.is_public_proxy.or(other.is_public_proxy);
+		self.is_residential_proxy = self.is_residential_proxy.or(other.is_residential_proxy);
+		self.is_tor_exit_node = self.is_tor_exit_node.or(other.is_tor_exit_node);
+		self.connection_type = self.connection_type.take().or(other.connection_type);
+		self.isp = self.isp.take().or(other.isp);
+		self.organization = self.organization.take().or(other.organization);
+		self.domain = self.domain.take().or(other.domain);
+		self.mobile_country_code = self.mobile_country_code.take().or(other.mobile_country_code);
+		self.mobile_network_code = self.mobile_network_code.take().or(other.mobile_network_code);
+		self.user_type = self.user_type.take().or(other.user_type);
+	}
 }
 
 #[derive(Debug, Clone, Serialize)]
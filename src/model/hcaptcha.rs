@@ -0,0 +1,21 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HCaptchaVerifyRequest {
+	pub secret: String,
+	pub response: String,
+	#[serde(rename = "remoteip", skip_serializing_if = "Option::is_none")]
+	pub remote_ip: Option<String>,
+	#[serde(rename = "sitekey", skip_serializing_if = "Option::is_none")]
+	pub site_key: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct HCaptchaVerifyResponse {
+	pub success: bool,
+	pub challenge_ts: Option<String>,
+	pub hostname: Option<String>,
+	pub credit: Option<bool>,
+	#[serde(default, rename = "error-codes")]
+	pub error_codes: Vec<String>,
+}
@@ -2,10 +2,20 @@ mod errors;
 mod geoip;
 mod templates;
 mod recaptcha;
+mod turnstile;
+mod hcaptcha;
 mod files;
+mod updates;
+mod versions;
+mod timezones;
 
 pub use errors::*;
 pub use geoip::*;
 pub use templates::*;
 pub use recaptcha::*;
+pub use turnstile::*;
+pub use hcaptcha::*;
 pub use files::*;
+pub use updates::*;
+pub use versions::*;
+pub use timezones::*;
@@ -1,9 +1,14 @@
 use serde::{Deserialize, Serialize};
+use crate::config::CaptchaProvider;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct IndexPageCtx {
+	pub captcha_provider: CaptchaProvider,
 	#[serde(skip_serializing_if = "Option::is_none")]
-	pub recaptcha_site_key: Option<String>,
+	pub captcha_site_key: Option<String>,
+	/// Seconds captcha session cookie is valid for, if issued.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub captcha_session_ttl: Option<u64>,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	pub osm_tiles_url: Option<String>,
 }
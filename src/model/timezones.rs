@@ -0,0 +1,84 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimezoneQuery {
+	/// Number of upcoming transitions to return.
+	pub transitions: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimezoneTransition {
+	pub time: DateTime<Utc>,
+	/// Seconds east of UTC after transition.
+	pub utc_offset: i32,
+	pub abbreviation: String,
+	pub dst: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimezoneDetails {
+	pub id: String,
+	/// Canonical id, equal to `id` unless it's an alias.
+	pub canonical: String,
+	/// Aliases of canonical timezone.
+	pub aliases: Vec<String>,
+	pub posix_timezone: String,
+	/// Current offset in seconds east of UTC.
+	pub utc_offset: i32,
+	pub abbreviation: String,
+	pub dst: bool,
+	pub transitions: Vec<TimezoneTransition>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub enum TimezoneListFormat {
+	#[default]
+	#[serde(rename = "json")]
+	Json,
+	/// `id,posix_timezone` with header row.
+	#[serde(rename = "csv")]
+	Csv,
+	/// C header with array of id and POSIX TZ string pairs.
+	#[serde(rename = "c-header")]
+	CHeader,
+	/// Layout of `zones.csv` from `posix_tz_db`, quoted fields without header row.
+	#[serde(rename = "zones.csv")]
+	ZonesCsv,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimezoneListQuery {
+	/// Comma-separated id prefixes, e.g. `Europe/`, everything if omitted.
+	pub prefix: Option<String>,
+	#[serde(default)]
+	pub format: TimezoneListFormat,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimezoneSource {
+	/// Zoneinfo of operating system.
+	#[default]
+	System,
+	/// Downloaded tzdata archive compiled by zic or built-in compiler.
+	Archive,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TimezoneDatabaseStatus {
+	pub source: TimezoneSource,
+	/// Timezone database release, e.g. `2025b`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub version: Option<String>,
+	/// Number of canonical timezones.
+	pub zones: usize,
+	/// Number of aliases.
+	pub links: usize,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub timestamp: Option<DateTime<Utc>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub last_update_check: Option<DateTime<Utc>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
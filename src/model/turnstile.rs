@@ -0,0 +1,20 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TurnstileVerifyRequest {
+	pub secret: String,
+	pub response: String,
+	#[serde(rename = "remoteip", skip_serializing_if = "Option::is_none")]
+	pub remote_ip: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TurnstileVerifyResponse {
+	pub success: bool,
+	pub challenge_ts: Option<String>,
+	pub hostname: Option<String>,
+	pub action: Option<String>,
+	pub cdata: Option<String>,
+	#[serde(default, rename = "error-codes")]
+	pub error_codes: Vec<String>,
+}
@@ -0,0 +1,37 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UpdateTriggerQuery {
+	pub target: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UpdateResult {
+	pub target: String,
+	pub updated: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UpdateStatus {
+	pub target: String,
+	pub running: bool,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub last_success: Option<DateTime<Utc>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub last_failure: Option<DateTime<Utc>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub last_error: Option<String>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub next_update: Option<DateTime<Utc>>,
+	#[serde(skip_serializing_if = "is_zero")]
+	pub retry_attempts: u32,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub next_retry: Option<DateTime<Utc>>,
+}
+
+fn is_zero(value: &u32) -> bool {
+	*value == 0
+}
@@ -0,0 +1,26 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VersionPinQuery {
+	/// Defaults to currently loaded version.
+	pub timestamp: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ArchiveVersion {
+	/// Archive release timestamp, identifies version.
+	pub timestamp: DateTime<Utc>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub archive_file_size: Option<u64>,
+	pub pinned: bool,
+	pub active: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EditionVersions {
+	pub edition: String,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub pinned: Option<DateTime<Utc>>,
+	pub versions: Vec<ArchiveVersion>,
+}
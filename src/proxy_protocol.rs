@@ -0,0 +1,356 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use ipnetwork::IpNetwork;
+use log::{error, warn};
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+#[derive(Debug, Error)]
+pub enum ProxyProtocolError {
+	#[error(transparent)]
+	Io(#[from] io::Error),
+	
+	#[error("{0}")]
+	Invalid(&'static str),
+}
+
+/// Original source address of connection accepted by [`ProxyProtocolListener`].
+#[derive(Debug, Clone, Copy)]
+pub struct ProxiedAddr(pub SocketAddr);
+
+impl Connected<IncomingStream<'_, ProxyProtocolListener>> for ProxiedAddr {
+	fn connect_info(stream: IncomingStream<'_, ProxyProtocolListener>) -> Self {
+		*stream.remote_addr()
+	}
+}
+
+/// TCP listener expecting PROXY protocol (v1 or v2) header on every
+/// accepted connection. Only connections from trusted proxies are accepted,
+/// anyone else could spoof source address. Headers are read concurrently,
+/// so slow clients don't block accepting other connections.
+pub struct ProxyProtocolListener {
+	local_addr: SocketAddr,
+	incoming: mpsc::Receiver<(TcpStream, ProxiedAddr)>,
+}
+
+impl ProxyProtocolListener {
+	pub fn new(listener: TcpListener, trusted_proxies: Vec<IpNetwork>) -> io::Result<Self> {
+		let local_addr = listener.local_addr()?;
+		let (tx, rx) = mpsc::channel(128);
+		tokio::spawn(Self::accept_loop(listener, trusted_proxies, tx));
+		Ok(Self {
+			local_addr,
+			incoming: rx,
+		})
+	}
+	
+	async fn accept_loop(
+		listener: TcpListener,
+		trusted_proxies: Vec<IpNetwork>,
+		tx: mpsc::Sender<(TcpStream, ProxiedAddr)>,
+	) {
+		while !tx.is_closed() {
+			let (mut stream, peer) = match listener.accept().await {
+				Ok(accepted) => accepted,
+				Err(err) => {
+					error!("Unable to accept connection: {err}");
+					tokio::time::sleep(Duration::from_secs(1)).await;
+					continue;
+				}
+			};
+			let peer_ip = peer.ip().to_canonical();
+			if !trusted_proxies.iter().any(|network| network.contains(peer_ip)) {
+				warn!("Dropping connection from {peer}, it is not a trusted proxy");
+				continue;
+			}
+			let tx = tx.clone();
+			tokio::spawn(async move {
+				match tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream)).await {
+					Ok(Ok(addr)) => {
+						let _ = tx.send((stream, ProxiedAddr(addr.unwrap_or(peer)))).await;
+					},
+					Ok(Err(err)) => warn!("Invalid PROXY protocol header from {peer}: {err}"),
+					Err(_) => warn!("Timed out reading PROXY protocol header from {peer}"),
+				}
+			});
+		}
+	}
+}
+
+impl Listener for ProxyProtocolListener {
+	type Io = TcpStream;
+	type Addr = ProxiedAddr;
+	
+	async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+		match self.incoming.recv().await {
+			Some(accepted) => accepted,
+			None => std::future::pending().await,
+		}
+	}
+	
+	fn local_addr(&self) -> io::Result<Self::Addr> {
+		Ok(ProxiedAddr(self.local_addr))
+	}
+}
+
+/// Reads PROXY protocol header, returns `None` for connections
+/// not carrying source address (health checks, `UNKNOWN` or `LOCAL`).
+async fn read_header(
+	stream: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+	// Shortest v1 header ("PROXY UNKNOWN\r\n") is longer than v2 signature
+	let mut buf = [0u8; 12];
+	stream.read_exact(&mut buf).await?;
+	if buf == V2_SIGNATURE {
+		read_v2_header(stream).await
+	} else if buf.starts_with(b"PROXY ") {
+		read_v1_header(stream, &buf).await
+	} else {
+		Err(ProxyProtocolError::Invalid("missing header"))
+	}
+}
+
+async fn read_v1_header(
+	stream: &mut (impl AsyncRead + Unpin),
+	prefix: &[u8],
+) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+	let mut line = prefix.to_vec();
+	while !line.ends_with(b"\r\n") {
+		if line.len() >= V1_MAX_LENGTH {
+			return Err(ProxyProtocolError::Invalid("v1 header too long"));
+		}
+		line.push(stream.read_u8().await?);
+	}
+	let line = std::str::from_utf8(&line[..line.len() - 2])
+		.map_err(|_| ProxyProtocolError::Invalid("v1 header is not valid text"))?;
+	let fields: Vec<_> = line.split(' ').collect();
+	match fields.as_slice() {
+		["PROXY", "UNKNOWN", ..] => Ok(None),
+		["PROXY", proto @ ("TCP4" | "TCP6"), src, _dst, src_port, _dst_port] => {
+			let ip: IpAddr = src.parse()
+				.map_err(|_| ProxyProtocolError::Invalid("invalid v1 source address"))?;
+			if ip.is_ipv4() != (*proto == "TCP4") {
+				return Err(ProxyProtocolError::Invalid("v1 address family mismatch"));
+			}
+			let port = src_port.parse()
+				.map_err(|_| ProxyProtocolError::Invalid("invalid v1 source port"))?;
+			Ok(Some(SocketAddr::new(ip, port)))
+		},
+		_ => Err(ProxyProtocolError::Invalid("malformed v1 header")),
+	}
+}
+
+async fn read_v2_header(
+	stream: &mut (impl AsyncRead + Unpin),
+) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+	let mut header = [0u8; 4];
+	stream.read_exact(&mut header).await?;
+	let [ver_cmd, family, len @ ..] = header;
+	if ver_cmd >> 4 != 2 {
+		return Err(ProxyProtocolError::Invalid("unsupported version"));
+	}
+	let mut payload = vec![0u8; u16::from_be_bytes(len) as usize];
+	stream.read_exact(&mut payload).await?;
+	match ver_cmd & 0x0F {
+		// LOCAL
+		0 => return Ok(None),
+		// PROXY
+		1 => {},
+		_ => return Err(ProxyProtocolError::Invalid("unsupported v2 command")),
+	}
+	let port = |offset: usize| u16::from_be_bytes([payload[offset], payload[offset + 1]]);
+	match family >> 4 {
+		// AF_INET
+		1 => {
+			if payload.len() < 12 {
+				return Err(ProxyProtocolError::Invalid("truncated v2 addresses"));
+			}
+			let src: [u8; 4] = payload[..4].try_into().expect("Invalid slice length");
+			Ok(Some(SocketAddr::new(Ipv4Addr::from(src).into(), port(8))))
+		},
+		// AF_INET6
+		2 => {
+			if payload.len() < 36 {
+				return Err(ProxyProtocolError::Invalid("truncated v2 addresses"));
+			}
+			let src: [u8; 16] = payload[..16].try_into().expect("Invalid slice length");
+			Ok(Some(SocketAddr::new(Ipv6Addr::from(src).into(), port(32))))
+		},
+		// AF_UNSPEC, AF_UNIX
+		_ => Ok(None),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tokio::io::AsyncWriteExt;
+	use super::*;
+	
+	async fn listen(trusted_proxy: &str) -> (ProxyProtocolListener, SocketAddr) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.expect("Unable to bind");
+		let addr = listener.local_addr().expect("Unable to get local address");
+		let trusted_proxies = vec![trusted_proxy.parse().expect("Invalid network")];
+		let listener = ProxyProtocolListener::new(listener, trusted_proxies)
+			.expect("Unable to create listener");
+		(listener, addr)
+	}
+	
+	#[tokio::test]
+	async fn accepts_header_from_trusted_proxy() {
+		let (mut listener, addr) = listen("127.0.0.0/8").await;
+		let mut client = TcpStream::connect(addr).await.expect("Unable to connect");
+		client.write_all(b"PROXY TCP4 203.0.113.7 127.0.0.1 41234 80\r\nGET /").await
+			.expect("Unable to write");
+		let (mut stream, ProxiedAddr(source)) = listener.accept().await;
+		assert_eq!(source, "203.0.113.7:41234".parse().expect("Invalid address"));
+		let mut rest = [0u8; 5];
+		stream.read_exact(&mut rest).await.expect("Unable to read");
+		assert_eq!(&rest, b"GET /");
+	}
+	
+	#[tokio::test]
+	async fn drops_connection_from_untrusted_peer() {
+		let (mut listener, addr) = listen("10.0.0.0/8").await;
+		let mut client = TcpStream::connect(addr).await.expect("Unable to connect");
+		// Spoofing address of trusted proxy must not help
+		let _ = client.write_all(b"PROXY TCP4 10.0.0.1 127.0.0.1 41234 80\r\n").await;
+		let mut buf = [0u8; 1];
+		let read = tokio::time::timeout(Duration::from_secs(5), client.read(&mut buf)).await
+			.expect("Connection was not closed");
+		assert!(matches!(read, Ok(0) | Err(_)));
+		let accepted = tokio::time::timeout(Duration::from_millis(100), listener.accept()).await;
+		assert!(accepted.is_err());
+	}
+	
+	async fn parse(mut header: &[u8]) -> Result<Option<SocketAddr>, ProxyProtocolError> {
+		read_header(&mut header).await
+	}
+	
+	fn addr(addr: &str) -> Option<SocketAddr> {
+		Some(addr.parse().expect("Invalid address"))
+	}
+	
+	fn v2_header(ver_cmd: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+		let mut header = V2_SIGNATURE.to_vec();
+		header.extend([ver_cmd, family]);
+		header.extend((addresses.len() as u16).to_be_bytes());
+		header.extend(addresses);
+		header
+	}
+	
+	#[tokio::test]
+	async fn parses_v1_header() {
+		assert_eq!(
+			parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n").await.unwrap(),
+			addr("192.0.2.1:56324"),
+		);
+		assert_eq!(
+			parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\n").await.unwrap(),
+			addr("[2001:db8::1]:56324"),
+		);
+		assert_eq!(parse(b"PROXY UNKNOWN\r\n").await.unwrap(), None);
+		assert_eq!(
+			parse(b"PROXY UNKNOWN ffff:f...f:ffff ffff:f...f:ffff 65535 65535\r\n").await.unwrap(),
+			None,
+		);
+	}
+	
+	#[tokio::test]
+	async fn rejects_malformed_v1_header() {
+		let headers: [&[u8]; 8] = [
+			b"GET / HTTP/1.1\r\n\r\n",
+			b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n",
+			b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443 extra\r\n",
+			b"PROXY TCP4 2001:db8::1 198.51.100.1 56324 443\r\n",
+			b"PROXY TCP6 192.0.2.1 198.51.100.1 56324 443\r\n",
+			b"PROXY TCP4 192.0.2.256 198.51.100.1 56324 443\r\n",
+			b"PROXY TCP4 192.0.2.1 198.51.100.1 65536 443\r\n",
+			b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n",
+		];
+		for header in headers {
+			assert!(
+				matches!(parse(header).await, Err(ProxyProtocolError::Invalid(_))),
+				"{}", String::from_utf8_lossy(header),
+			);
+		}
+	}
+	
+	#[tokio::test]
+	async fn rejects_unterminated_v1_header() {
+		let mut header = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443".to_vec();
+		header.resize(200, b' ');
+		assert!(matches!(parse(&header).await, Err(ProxyProtocolError::Invalid(_))));
+		let header = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443";
+		assert!(matches!(parse(header).await, Err(ProxyProtocolError::Io(_))));
+	}
+	
+	#[tokio::test]
+	async fn parses_v2_header() {
+		let mut ipv4 = vec![192, 0, 2, 1, 198, 51, 100, 1];
+		ipv4.extend(56324u16.to_be_bytes());
+		ipv4.extend(443u16.to_be_bytes());
+		assert_eq!(parse(&v2_header(0x21, 0x11, &ipv4)).await.unwrap(), addr("192.0.2.1:56324"));
+		
+		let src: Ipv6Addr = "2001:db8::1".parse().unwrap();
+		let dst: Ipv6Addr = "2001:db8::2".parse().unwrap();
+		let mut ipv6 = src.octets().to_vec();
+		ipv6.extend(dst.octets());
+		ipv6.extend(56324u16.to_be_bytes());
+		ipv6.extend(443u16.to_be_bytes());
+		assert_eq!(
+			parse(&v2_header(0x21, 0x21, &ipv6)).await.unwrap(),
+			addr("[2001:db8::1]:56324"),
+		);
+		
+		// TLVs after addresses are skipped
+		ipv4.extend([0x04, 0x00, 0x01, 0x00]);
+		let mut header = v2_header(0x21, 0x11, &ipv4);
+		header.extend(b"GET /");
+		let mut stream = header.as_slice();
+		assert_eq!(read_header(&mut stream).await.unwrap(), addr("192.0.2.1:56324"));
+		assert_eq!(stream, b"GET /");
+	}
+	
+	#[tokio::test]
+	async fn parses_v2_header_without_address() {
+		// LOCAL command is used by health checks, addresses are ignored
+		assert_eq!(parse(&v2_header(0x20, 0x11, &[0; 12])).await.unwrap(), None);
+		// AF_UNSPEC
+		assert_eq!(parse(&v2_header(0x21, 0x00, &[])).await.unwrap(), None);
+		// AF_UNIX
+		assert_eq!(parse(&v2_header(0x21, 0x31, &[0; 216])).await.unwrap(), None);
+	}
+	
+	#[tokio::test]
+	async fn rejects_malformed_v2_header() {
+		assert!(matches!(
+			parse(&v2_header(0x11, 0x11, &[0; 12])).await,
+			Err(ProxyProtocolError::Invalid("unsupported version")),
+		));
+		assert!(matches!(
+			parse(&v2_header(0x22, 0x11, &[0; 12])).await,
+			Err(ProxyProtocolError::Invalid("unsupported v2 command")),
+		));
+		assert!(matches!(
+			parse(&v2_header(0x21, 0x11, &[0; 8])).await,
+			Err(ProxyProtocolError::Invalid("truncated v2 addresses")),
+		));
+		assert!(matches!(
+			parse(&v2_header(0x21, 0x21, &[0; 12])).await,
+			Err(ProxyProtocolError::Invalid("truncated v2 addresses")),
+		));
+		// Declared length is longer than data
+		let mut header = v2_header(0x21, 0x11, &[0; 12]);
+		header.truncate(header.len() - 4);
+		assert!(matches!(parse(&header).await, Err(ProxyProtocolError::Io(_))));
+	}
+}
@@ -13,6 +13,7 @@ use chrono::{DateTime, NaiveDateTime, Utc};
 use log::{error, info};
 use regex::Regex;
 use reqwest::{header, Client, RequestBuilder, StatusCode};
+use sha2::{Digest, Sha256};
 use tempfile::NamedTempFile;
 use thiserror::Error;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
@@ -32,54 +33,150 @@ pub enum FileServiceError {
 	
 	#[error(transparent)]
 	Reqwest(#[from] reqwest::Error),
+	
+	#[error("HTTP error (status={status})")]
+	Http {
+		status: StatusCode,
+		retry_after: Option<Duration>,
+	},
+	
+	#[error("Invalid checksum file {0}")]
+	InvalidChecksum(String),
+	
+	#[error("Checksum mismatch (expected={expected}, actual={actual})")]
+	ChecksumMismatch {
+		expected: String,
+		actual: String,
+	},
+}
+
+impl FileServiceError {
+	/// Whether failed request makes sense to retry later
+	/// (network errors, server errors and rate limiting).
+	pub fn is_transient(&self) -> bool {
+		match self {
+			FileServiceError::Io(_) => false,
+			FileServiceError::Reqwest(_) => true,
+			FileServiceError::InvalidChecksum(_) => false,
+			FileServiceError::ChecksumMismatch { .. } => true,
+			FileServiceError::Http { status, .. } => {
+				status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+			},
+		}
+	}
+	
+	pub fn retry_after(&self) -> Option<Duration> {
+		match self {
+			FileServiceError::Http { retry_after, .. } => *retry_after,
+			_ => None,
+		}
+	}
 }
 
+/// Retained archives of a single tag, the newest first.
+type ArchiveVersions = im::Vector<Arc<ArchiveFileInfo>>;
+
 pub struct FileService {
 	me: Weak<Self>,
 	config: Arc<AppConfig>,
 	client: Client,
-	archives: ArcSwap<im::HashMap<String, Arc<ArchiveFileInfo>>>,
+	archives: ArcSwap<im::HashMap<String, ArchiveVersions>>,
+	/// Versions (archive timestamps) tags are pinned to.
+	pins: ArcSwap<im::HashMap<String, DateTime<Utc>>>,
+	read_only: bool,
 }
 
 impl FileService {
 	pub async fn new(config: Arc<AppConfig>, client: Client) -> Arc<Self> {
-		let archives = Self::find_and_cleanup_archives(&config.data_dir).await;
+		Self::open(config, client, false).await
+	}
+	
+	/// Opens `DATA_DIR` for offline inspection, nothing there is created,
+	/// deleted or modified, so it is safe to use next to running server.
+	pub async fn new_read_only(config: Arc<AppConfig>, client: Client) -> Arc<Self> {
+		Self::open(config, client, true).await
+	}
+	
+	async fn open(config: Arc<AppConfig>, client: Client, read_only: bool) -> Arc<Self> {
+		let (archives, pins) = Self::find_and_cleanup_archives(&config, read_only).await;
 		
 		Arc::new_cyclic(|me| Self {
 			me: me.clone(),
 			config,
 			client,
 			archives: ArcSwap::new(archives),
+			pins: ArcSwap::new(pins),
+			read_only,
 		})
 	}
 	
-	async fn find_and_cleanup_archives(
-		data_dir: &Path,
-	) -> Arc<im::HashMap<String, Arc<ArchiveFileInfo>>> {
-		let mut out = AHashMap::new();
-		if let Err(err) = fs::create_dir_all(&data_dir).await {
+	/// Whether files must be used as they are, without extracting
+	/// or deleting anything.
+	pub fn is_read_only(&self) -> bool {
+		self.read_only
+	}
+	
+	fn check_writable(&self) -> Result<(), io::Error> {
+		if self.read_only {
+			return Err(io::Error::new(
+				io::ErrorKind::ReadOnlyFilesystem,
+				"Data directory is opened read-only",
+			));
+		}
+		Ok(())
+	}
+	
+	async fn find_and_cleanup_archives(config: &AppConfig, read_only: bool) -> (
+		Arc<im::HashMap<String, ArchiveVersions>>,
+		Arc<im::HashMap<String, DateTime<Utc>>>,
+	) {
+		let data_dir = &config.data_dir;
+		let mut found = AHashMap::new();
+		if !read_only && let Err(err) = fs::create_dir_all(&data_dir).await {
 			error!("Unable to create data directory: {err}");
 		}
 		match fs::read_dir(data_dir).await {
 			Ok(entries) => {
-				Self::find_and_cleanup_archives_by_read_dir(data_dir, entries, &mut out).await;
+				Self::find_archives_by_read_dir(data_dir, entries, &mut found).await;
 			},
 			Err(err) => {
 				error!("Unable to read directory {}: {err}", data_dir.display());
 			}
 		}
-		Arc::new(out.into_iter().collect())
+		let mut archives = im::HashMap::new();
+		let mut pins = im::HashMap::new();
+		for (tag, versions) in found {
+			let pinned = Self::read_pin(data_dir, &tag).await;
+			// Nothing is deleted in read-only mode, so all archives stay usable
+			let retention = if read_only { 0 } else { config.archive_retention(&tag) };
+			let (retained, removed) = Self::apply_retention(
+				versions.into_iter().collect(),
+				retention,
+				pinned,
+			);
+			if !read_only {
+				for info in removed {
+					Self::cleanup_archive(&info).await;
+				}
+			}
+			if let Some(pinned) = pinned {
+				pins.insert(tag.clone(), pinned);
+			}
+			archives.insert(tag, retained);
+		}
+		(Arc::new(archives), Arc::new(pins))
 	}
 	
-	async fn find_and_cleanup_archives_by_read_dir(
+	async fn find_archives_by_read_dir(
 		path: &Path,
 		mut entries: fs::ReadDir,
-		out: &mut AHashMap<String, Arc<ArchiveFileInfo>>,
+		out: &mut AHashMap<String, Vec<Arc<ArchiveFileInfo>>>,
 	) {
 		loop {
 			match entries.next_entry().await {
 				Ok(Some(entry)) => {
-					Self::find_and_cleanup_archive_by_dir_entry(entry, out).await;
+					let Some(info) = Self::archive_info_from_path(entry.path()).await else { continue };
+					out.entry(info.tag.clone()).or_default().push(info);
 				}
 				Ok(None) => break,
 				Err(err) => {
@@ -90,19 +187,31 @@ impl FileService {
 		}
 	}
 	
-	async fn find_and_cleanup_archive_by_dir_entry(
-		entry: fs::DirEntry,
-		out: &mut AHashMap<String, Arc<ArchiveFileInfo>>,
-	) {
-		let Some(info) = Self::archive_info_from_path(entry.path()).await else { return };
-		if let Some(old) = out.insert(info.tag.clone(), info.clone()) {
-			if old.mtime < info.mtime {
-				Self::cleanup_archive(&old).await;
+	async fn read_pin(data_dir: &Path, tag: &str) -> Option<DateTime<Utc>> {
+		let pin = fs::read_to_string(data_dir.join(format!("{tag}.pin"))).await.ok()?;
+		let pin = NaiveDateTime::parse_from_str(pin.trim(), TIMESTAMP_FORMAT).ok()?;
+		Some(pin.and_utc())
+	}
+	
+	/// Sorts `versions` newest first and splits them into retained ones
+	/// (newest `retention` versions, all if zero, and the pinned one)
+	/// and the rest.
+	fn apply_retention(
+		mut versions: Vec<Arc<ArchiveFileInfo>>,
+		retention: usize,
+		pinned: Option<DateTime<Utc>>,
+	) -> (ArchiveVersions, Vec<Arc<ArchiveFileInfo>>) {
+		versions.sort_by_key(|info| std::cmp::Reverse(info.mtime));
+		let mut retained = ArchiveVersions::new();
+		let mut removed = Vec::new();
+		for (i, info) in versions.into_iter().enumerate() {
+			if retention == 0 || i < retention || Some(info.mtime) == pinned {
+				retained.push_back(info);
 			} else {
-				out.insert(info.tag.clone(), old);
-				Self::cleanup_archive(&info).await;
+				removed.push(info);
 			}
 		}
+		(retained, removed)
 	}
 	
 	async fn archive_info_from_path(path: PathBuf) -> Option<Arc<ArchiveFileInfo>> {
@@ -127,16 +236,23 @@ impl FileService {
 		}))
 	}
 	
-	pub async fn cleanup_archive(info: &ArchiveFileInfo) {
+	async fn cleanup_archive(info: &ArchiveFileInfo) {
+		// Pin file may have been written by CLI meanwhile
+		if let Some(data_dir) = info.path.parent()
+			&& Self::read_pin(data_dir, &info.tag).await == Some(info.mtime)
+		{
+			info!("Keeping pinned archive: {}", info.path.display());
+			return;
+		}
 		info!("Deleting archive: {}", info.path.display());
-		if let Some(data_dir) = info.path.parent() {
-			if let Err(err) = Self::cleanup_data_dir(
+		if let Some(data_dir) = info.path.parent()
+			&& let Err(err) = Self::cleanup_data_dir(
 				data_dir,
 				&format!("{}-{}", info.tag, info.mtime.format(TIMESTAMP_FORMAT)),
 				&info.path,
-			).await {
-				error!("Unable to cleanup {}: {err}", info.path.display());
-			}
+			).await
+		{
+			error!("Unable to cleanup {}: {err}", info.path.display());
 		}
 		if let Err(err) = fs::remove_file(&info.path).await {
 			error!("Unable to remove {}: {err}", info.path.display());
@@ -176,17 +292,141 @@ impl FileService {
 		Ok(())
 	}
 	
+	/// Removes archive from retained versions and deletes it.
+	pub async fn remove_archive(&self, info: &ArchiveFileInfo) {
+		if self.read_only {
+			return;
+		}
+		self.archives.rcu(|archives| {
+			let Some(versions) = archives.get(&info.tag) else { return archives.clone() };
+			let versions = versions.iter()
+				.filter(|version| version.mtime != info.mtime)
+				.cloned()
+				.collect();
+			Arc::new(archives.update(info.tag.clone(), versions))
+		});
+		Self::cleanup_archive(info).await;
+	}
+	
+	/// Deletes archive once nobody uses it anymore.
+	fn schedule_cleanup(info: Arc<ArchiveFileInfo>) {
+		let cloned_info = (*info).clone();
+		let info_weak = Arc::downgrade(&info);
+		drop(info);
+		tokio::spawn(async move {
+			while info_weak.upgrade().is_some() {
+				tokio::time::sleep(Duration::from_millis(100)).await;
+			}
+			Self::cleanup_archive(&cloned_info).await;
+		});
+	}
+	
 	pub fn get_latest_archive(&self, tag: &str) -> Option<Arc<ArchiveFileInfo>> {
-		self.archives.load().get(tag).cloned()
+		self.archives.load().get(tag).and_then(|versions| versions.front().cloned())
+	}
+	
+	/// Returns retained archives of `tag`, the newest first.
+	pub fn list_archives(&self, tag: &str) -> Vec<Arc<ArchiveFileInfo>> {
+		self.archives.load()
+			.get(tag)
+			.map(|versions| versions.iter().cloned().collect())
+			.unwrap_or_default()
+	}
+	
+	pub fn get_archive(&self, tag: &str, mtime: DateTime<Utc>) -> Option<Arc<ArchiveFileInfo>> {
+		self.archives.load()
+			.get(tag)?
+			.iter()
+			.find(|info| info.mtime == mtime)
+			.cloned()
+	}
+	
+	/// Returns pinned archive of `tag` (if it is still retained),
+	/// otherwise the latest one.
+	pub fn get_current_archive(&self, tag: &str) -> Option<Arc<ArchiveFileInfo>> {
+		self.get_pinned_version(tag)
+			.and_then(|pinned| self.get_archive(tag, pinned))
+			.or_else(|| self.get_latest_archive(tag))
+	}
+	
+	pub fn get_pinned_version(&self, tag: &str) -> Option<DateTime<Utc>> {
+		self.pins.load().get(tag).copied()
+	}
+	
+	/// Pins `tag` to archive version `mtime` (or unpins it if `None`),
+	/// pinned version survives restarts and is never deleted.
+	pub async fn set_pinned_version(
+		&self,
+		tag: &str,
+		mtime: Option<DateTime<Utc>>,
+	) -> Result<(), io::Error> {
+		self.check_writable()?;
+		self.write_pin(tag, mtime).await?;
+		self.prune_archives(tag).await;
+		Ok(())
+	}
+	
+	/// Only writes (or removes if `None`) pin file of `tag`, so it is allowed
+	/// in read-only mode too. Running server picks it up on restart.
+	pub async fn write_pin(&self, tag: &str, mtime: Option<DateTime<Utc>>) -> Result<(), io::Error> {
+		let pin_file = format!("{tag}.pin");
+		match mtime {
+			Some(mtime) => {
+				self.write_file(&pin_file, &mtime.format(TIMESTAMP_FORMAT).to_string()).await?;
+			},
+			None => {
+				match fs::remove_file(self.config.data_dir.join(&pin_file)).await {
+					Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+					_ => {},
+				}
+			},
+		}
+		self.set_pin(tag, mtime);
+		Ok(())
+	}
+	
+	fn set_pin(&self, tag: &str, mtime: Option<DateTime<Utc>>) {
+		self.pins.rcu(|pins| Arc::new(match mtime {
+			Some(mtime) => pins.update(tag.to_owned(), mtime),
+			None => pins.without(tag),
+		}));
+	}
+	
+	/// Drops versions of `tag` exceeding retention,
+	/// they are deleted once nobody uses them.
+	async fn prune_archives(&self, tag: &str) {
+		let retention = self.config.archive_retention(tag);
+		// Pin file may have been changed by CLI since startup
+		let pinned = Self::read_pin(&self.config.data_dir, tag).await;
+		self.set_pin(tag, pinned);
+		let mut removed = Vec::new();
+		self.archives.rcu(|archives| {
+			let Some(versions) = archives.get(tag) else { return archives.clone() };
+			let retained;
+			(retained, removed) = Self::apply_retention(
+				versions.iter().cloned().collect(),
+				retention,
+				pinned,
+			);
+			Arc::new(archives.update(tag.to_owned(), retained))
+		});
+		for info in removed {
+			Self::schedule_cleanup(info);
+		}
 	}
 	
+	/// Downloads archive `tag` from `url` unless the latest one is fresher than
+	/// `interval`. If `checksum_url` is set, downloaded archive is verified against
+	/// SHA-256 checksum published there and discarded on mismatch.
 	pub async fn refresh_archive(
 		&self,
 		tag: &str,
 		url: &str,
+		checksum_url: Option<&str>,
 		auth: ArchiveFileAuth,
 		interval: Duration,
 	) -> Result<Option<Arc<ArchiveFileInfo>>, FileServiceError> {
+		self.check_writable()?;
 		let now = Utc::now();
 		let mut req = self.client.get(url);
 		let info = self.get_latest_archive(tag);
@@ -197,10 +437,10 @@ impl FileService {
 			}
 			req = req.header(header::IF_MODIFIED_SINCE, httpdate::fmt_http_date(info.mtime.into()));
 		}
-		req = Self::setup_request_auth(req, auth);
-		let mut res = req.send().await?.error_for_status()?;
-		let status = res.status();
-		if status == StatusCode::NOT_MODIFIED {
+		req = Self::setup_request_auth(req, auth.clone());
+		let mut res = req.send().await?;
+		Self::check_status(&res)?;
+		if res.status() == StatusCode::NOT_MODIFIED {
 			self.set_refresh_timestamp(tag, now).await;
 			info!("{url} wasn't modified");
 			return Ok(None);
@@ -210,18 +450,30 @@ impl FileService {
 			.get(header::LAST_MODIFIED)
 			.and_then(|v| v.to_str().ok())
 			.and_then(|v| httpdate::parse_http_date(v).ok())
-			.map(|v| DateTime::<Utc>::from(v))
+			.map(DateTime::<Utc>::from)
 			.unwrap_or(now);
 		let (file, path) = self.new_named_temp_file().await?.into_parts();
 		let mut file = fs::File::from_std(file);
+		let mut hasher = Sha256::new();
 		while let Some(chunk) = res.chunk().await? {
+			hasher.update(&chunk);
 			file.write_all(&chunk).await?;
 		}
+		let checksum = format!("{:x}", hasher.finalize());
+		if let Some(checksum_url) = checksum_url {
+			let expected = self.fetch_checksum(checksum_url, auth).await?;
+			if expected != checksum {
+				return Err(FileServiceError::ChecksumMismatch {
+					expected,
+					actual: checksum,
+				});
+			}
+			info!("{tag} archive checksum verified");
+		}
 		let file = NamedTempFile::from_parts(file.into_std().await, path);
-		let path = self.persist_named_temp_file(
-			file,
-			&format!("{tag}-{}.tar.gz", mtime.format(TIMESTAMP_FORMAT)),
-		).await?;
+		let file_name = format!("{tag}-{}.tar.gz", mtime.format(TIMESTAMP_FORMAT));
+		let path = self.persist_named_temp_file(file, &file_name).await?;
+		self.store_file(&format!("{file_name}.sha256"), &checksum).await?;
 		self.store_refresh_timestamp(tag, now).await?;
 		info!("{tag} archive refreshed from {url}");
 		let new_info = Arc::new(ArchiveFileInfo {
@@ -231,20 +483,81 @@ impl FileService {
 			utime: now,
 		});
 		self.archives.rcu(|archives| {
-			Arc::new(archives.update(tag.to_owned(), new_info.clone()))
+			// Archive with the same timestamp (if any) was just overwritten
+			let mut versions: ArchiveVersions = archives.get(tag)
+				.map(|versions| versions.iter().filter(|info| info.mtime != mtime).cloned().collect())
+				.unwrap_or_default();
+			versions.push_front(new_info.clone());
+			Arc::new(archives.update(tag.to_owned(), versions))
 		});
-		if let Some(info) = info {
-			let cloned_info = (*info).clone();
-			let info_weak = Arc::downgrade(&info);
-			drop(info);
-			tokio::spawn(async move {
-				while info_weak.upgrade().is_some() {
-					tokio::time::sleep(Duration::from_millis(100)).await;
-				}
-				Self::cleanup_archive(&cloned_info).await;
+		drop(info);
+		self.prune_archives(tag).await;
+		Ok(Some(new_info))
+	}
+	
+	fn check_status(res: &reqwest::Response) -> Result<(), FileServiceError> {
+		let status = res.status();
+		if status.is_client_error() || status.is_server_error() {
+			return Err(FileServiceError::Http {
+				status,
+				retry_after: Self::parse_retry_after(res.headers()),
 			});
 		}
-		Ok(Some(new_info))
+		Ok(())
+	}
+	
+	/// Fetches checksum file in `sha256sum` format (`{hex}  {file name}`).
+	async fn fetch_checksum(
+		&self,
+		url: &str,
+		auth: ArchiveFileAuth,
+	) -> Result<String, FileServiceError> {
+		let req = Self::setup_request_auth(self.client.get(url), auth);
+		let res = req.send().await?;
+		Self::check_status(&res)?;
+		let body = res.text().await?;
+		body.split_whitespace()
+			.next()
+			.filter(|hex| hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+			.map(str::to_ascii_lowercase)
+			.ok_or_else(|| FileServiceError::InvalidChecksum(url.to_owned()))
+	}
+	
+	/// Returns SHA-256 checksum of archive, computing it (and storing next
+	/// to the archive) if it was downloaded before checksums were tracked.
+	pub async fn get_archive_checksum(&self, info: &ArchiveFileInfo) -> Result<String, FileServiceError> {
+		let checksum_path = Self::checksum_path(&info.path);
+		if let Ok(checksum) = fs::read_to_string(&checksum_path).await {
+			return Ok(checksum.trim().to_owned());
+		}
+		let path = info.path.clone();
+		let checksum = tokio::task::spawn_blocking(move || {
+			let mut file = std::fs::File::open(path)?;
+			let mut hasher = Sha256::new();
+			io::copy(&mut file, &mut hasher)?;
+			Ok::<_, io::Error>(format!("{:x}", hasher.finalize()))
+		}).await.map_err(io::Error::other)??;
+		if let Some(file_name) = checksum_path.file_name().and_then(|s| s.to_str())
+			&& let Err(err) = self.store_file(file_name, &checksum).await
+		{
+			error!("Unable to store {}: {err}", checksum_path.display());
+		}
+		Ok(checksum)
+	}
+	
+	fn checksum_path(path: &Path) -> PathBuf {
+		let mut path = path.as_os_str().to_owned();
+		path.push(".sha256");
+		PathBuf::from(path)
+	}
+	
+	fn parse_retry_after(headers: &header::HeaderMap) -> Option<Duration> {
+		let value = headers.get(header::RETRY_AFTER)?.to_str().ok()?.trim();
+		if let Ok(secs) = value.parse() {
+			return Some(Duration::from_secs(secs));
+		}
+		let date = httpdate::parse_http_date(value).ok()?;
+		Some(date.duration_since(SystemTime::now()).unwrap_or_default())
 	}
 	
 	fn setup_request_auth(req: RequestBuilder, auth: ArchiveFileAuth) -> RequestBuilder {
@@ -264,12 +577,15 @@ impl FileService {
 			error!("Unable to store refresh timestamp for {tag}: {err}");
 		}
 		self.archives.rcu(|archives| {
-			if let Some(info) = archives.get(tag) && info.utime < timestamp {
+			if let Some(versions) = archives.get(tag)
+				&& let Some(info) = versions.front()
+				&& info.utime < timestamp
+			{
 				let new_info = Arc::new(ArchiveFileInfo {
 					utime: timestamp,
 					..(**info).clone()
 				});
-				Arc::new(archives.update(tag.to_owned(), new_info))
+				Arc::new(archives.update(tag.to_owned(), versions.update(0, new_info)))
 			} else {
 				archives.clone()
 			}
@@ -281,11 +597,20 @@ impl FileService {
 		tag: &str,
 		timestamp: DateTime<Utc>,
 	) -> Result<(), io::Error> {
+		self.store_file(&format!("{tag}.timestamp"), &timestamp.to_rfc2822()).await
+	}
+	
+	async fn store_file(&self, name: &str, contents: &str) -> Result<(), io::Error> {
+		self.check_writable()?;
+		self.write_file(name, contents).await
+	}
+	
+	async fn write_file(&self, name: &str, contents: &str) -> Result<(), io::Error> {
 		let (file, path) = self.new_named_temp_file().await?.into_parts();
 		let mut file = fs::File::from_std(file);
-		file.write_all(timestamp.to_rfc2822().as_bytes()).await?;
+		file.write_all(contents.as_bytes()).await?;
 		let file = NamedTempFile::from_parts(file.into_std().await, path);
-		self.persist_named_temp_file(file, &format!("{tag}.timestamp")).await?;
+		self.persist_named_temp_file(file, name).await?;
 		Ok(())
 	}
 	
@@ -315,13 +640,13 @@ impl FileService {
 		file_name: &str,
 		if_modified_since: Option<TypedHeader<IfModifiedSince>>,
 	) -> Result<Response, FileServiceError> {
-		if let Some(if_modified_since) = if_modified_since {
-			if !if_modified_since.is_modified(info.mtime.into()) {
-				return Ok((
-					StatusCode::NOT_MODIFIED,
-					TypedHeader(LastModified::from(SystemTime::from(info.mtime))),
-				).into_response());
-			}
+		if let Some(if_modified_since) = if_modified_since
+			&& !if_modified_since.is_modified(info.mtime.into())
+		{
+			return Ok((
+				StatusCode::NOT_MODIFIED,
+				TypedHeader(LastModified::from(SystemTime::from(info.mtime))),
+			).into_response());
 		}
 		let file = fs::File::open(&info.path).await?;
 		let metadata = file.metadata().await?;
@@ -350,4 +675,18 @@ impl FileService {
 			stream,
 		).into_response())
 	}
+	
+	pub async fn download_archive_checksum(
+		&self,
+		info: Arc<ArchiveFileInfo>,
+		file_name: &str,
+	) -> Result<Response, FileServiceError> {
+		let checksum = self.get_archive_checksum(&info).await?;
+		Ok((
+			StatusCode::OK,
+			TypedHeader(LastModified::from(SystemTime::from(info.mtime))),
+			TypedHeader(ContentType::text_utf8()),
+			format!("{checksum}  {file_name}\n"),
+		).into_response())
+	}
 }
@@ -1,20 +1,25 @@
 use std::{fs, io};
+use std::collections::{BTreeMap, VecDeque};
 use std::ffi::OsStr;
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Weak};
+use std::sync::{Arc, Mutex, Weak};
 use std::time::Duration;
 use ahash::AHashMap;
 use arc_swap::ArcSwapOption;
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 use flate2::read::GzDecoder;
-use log::{error, info, warn};
+use ipnetwork::{IpNetwork, IpNetworkError};
+use log::{info, warn};
 use maxminddb::{geoip2, MaxMindDbError};
 use thiserror::Error;
 use tokio::time::MissedTickBehavior;
-use crate::config::{AppConfig, DOWNLOAD_URL_EDITION_PLACEHOLDER};
-use crate::model::{ArchiveFileAuth, ArchiveFileInfo, GeoIpDatabaseStatus, GeoIpInfo, GeoIpStatus, GeoNameSubdivision};
-use crate::state::{FileService, FileServiceError, TimezoneService};
+use crate::config::{AppConfig, EditionAuth, DOWNLOAD_URL_EDITION_PLACEHOLDER};
+use crate::model::{
+	ArchiveFileAuth, ArchiveFileInfo, ArchiveVersion, EditionVersions, GeoIpDatabaseMetadata,
+	GeoIpDatabaseStatus, GeoIpInfo, GeoIpStatus, GeoNameSubdivision, UpdateResult, UpdateStatus,
+};
+use crate::state::{FileService, FileServiceError, TimezoneService, UpdateTracker};
 
 #[derive(Debug, Error)]
 pub enum MaxMindServiceError {
@@ -30,11 +35,52 @@ pub enum MaxMindServiceError {
 	#[error(transparent)]
 	JoinError(#[from] tokio::task::JoinError),
 	
+	#[error(transparent)]
+	IpNetwork(#[from] IpNetworkError),
+	
 	#[error("Unknown MaxMind database edition")]
 	UnknownEdition,
 	
 	#[error("MaxMind database is missing")]
 	MissingDatabase,
+	
+	#[error("Unknown MaxMind database version")]
+	UnknownVersion,
+}
+
+impl MaxMindServiceError {
+	/// Whether failed update makes sense to retry before next
+	/// scheduled update.
+	pub fn is_transient(&self) -> bool {
+		match self {
+			MaxMindServiceError::FileService(err) => err.is_transient(),
+			MaxMindServiceError::MaxMindDb(_) | MaxMindServiceError::MissingDatabase => true,
+			_ => false,
+		}
+	}
+	
+	/// Whether error proves database archive is broken, as opposed
+	/// to I/O failures which may go away on their own.
+	pub fn is_corrupt(&self) -> bool {
+		match self {
+			MaxMindServiceError::MaxMindDb(
+				MaxMindDbError::InvalidDatabase(_) | MaxMindDbError::Decoding(_)
+			) => true,
+			MaxMindServiceError::Io(err) => matches!(
+				err.kind(),
+				io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof,
+			),
+			MaxMindServiceError::MissingDatabase => true,
+			_ => false,
+		}
+	}
+	
+	pub fn retry_after(&self) -> Option<Duration> {
+		match self {
+			MaxMindServiceError::FileService(err) => err.retry_after(),
+			_ => None,
+		}
+	}
 }
 
 struct MaxMindDbReader {
@@ -45,6 +91,22 @@ struct MaxMindDbReader {
 	info: Arc<ArchiveFileInfo>,
 }
 
+pub struct MaxMindLookup {
+	pub info: Option<GeoIpInfo>,
+	pub network: IpNetwork,
+	/// Versions of databases result was looked up in, by edition.
+	pub versions: BTreeMap<String, DateTime<Utc>>,
+}
+
+/// Picks database version for historical lookup.
+#[derive(Debug, Clone, Copy)]
+pub enum VersionSelector {
+	/// Version which was the latest one at given instant.
+	At(DateTime<Utc>),
+	/// Version with exactly given timestamp.
+	Exact(DateTime<Utc>),
+}
+
 pub struct MaxMindService {
 	me: Weak<Self>,
 	config: Arc<AppConfig>,
@@ -52,6 +114,10 @@ pub struct MaxMindService {
 	timezones: Arc<TimezoneService>,
 	readers: AHashMap<String, ArcSwapOption<MaxMindDbReader>>,
 	errors: AHashMap<String, ArcSwapOption<String>>,
+	updates: AHashMap<String, UpdateTracker>,
+	/// Recently used versions other than loaded ones, the most recent first.
+	historical_readers: Mutex<VecDeque<Arc<MaxMindDbReader>>>,
+	historical_open_lock: tokio::sync::Mutex<()>,
 }
 
 impl MaxMindService {
@@ -63,7 +129,11 @@ impl MaxMindService {
 		let (
 			readers,
 			errors,
-		) = Self::load_all_latest(&config, &files).await;
+		) = Self::load_all_current(&config, &files).await;
+		let updates = config.maxmind_editions
+			.iter()
+			.map(|edition| (edition.clone(), UpdateTracker::new(edition)))
+			.collect();
 		
 		Arc::new_cyclic(|me| Self {
 			me: me.clone(),
@@ -72,10 +142,16 @@ impl MaxMindService {
 			timezones,
 			readers,
 			errors,
+			updates,
+			historical_readers: Mutex::new(VecDeque::new()),
+			historical_open_lock: tokio::sync::Mutex::new(()),
 		})
 	}
 	
-	async fn load_all_latest(config: &AppConfig, files: &FileService) -> (
+	/// Loads pinned (or the latest) version of every edition. Corrupt
+	/// versions other than the pinned one are deleted and the next
+	/// retained one is tried, other failures are only reported.
+	async fn load_all_current(config: &AppConfig, files: &FileService) -> (
 		AHashMap<String, ArcSwapOption<MaxMindDbReader>>,
 		AHashMap<String, ArcSwapOption<String>>,
 	) {
@@ -84,17 +160,24 @@ impl MaxMindService {
 		for edition in &config.maxmind_editions {
 			errors.insert(edition.clone(), ArcSwapOption::new(None));
 			let out_err = errors.get(edition).expect("Unknown edition");
+			let pinned = files.get_pinned_version(edition);
 			let mut reader = None;
-			if let Some(info) = files.get_latest_archive(edition) {
-				match Self::load_from_archive(info.clone()) {
+			while let Some(info) = files.get_current_archive(edition) {
+				match Self::load_from_archive(info.clone(), !files.is_read_only()) {
 					Ok(r) => {
 						reader = Some(r);
-						out_err.store(None);
+						break;
 					}
 					Err(err) => {
-						warn!("Unable to open MaxMind {edition} database: {err}");
-						out_err.store(Some(Arc::new(err.to_string())));
-						FileService::cleanup_archive(&info).await;
+						warn!("Unable to open MaxMind {edition} database version {}: {err}", info.mtime);
+						out_err.store(Some(Arc::new(format!(
+							"Unable to open version {}: {err}",
+							info.mtime,
+						))));
+						if files.is_read_only() || !err.is_corrupt() || pinned == Some(info.mtime) {
+							break;
+						}
+						files.remove_archive(&info).await;
 					}
 				}
 			}
@@ -106,15 +189,17 @@ impl MaxMindService {
 		(out, errors)
 	}
 	
+	/// Maps database extracted from archive, extracting it first
+	/// if `extract` is set.
 	fn load_from_archive(
 		info: Arc<ArchiveFileInfo>,
+		extract: bool,
 	) -> Result<Arc<MaxMindDbReader>, MaxMindServiceError> {
 		let mut path = info.path.with_extension("");
 		path.set_extension("mmdb");
-		if !path.exists() {
-			if !Self::extract_mmdb(&info.path, &path)? {
-				return Err(MaxMindServiceError::MissingDatabase);
-			}
+		let available = path.exists() || (extract && Self::extract_mmdb(&info.path, &path)?);
+		if !available {
+			return Err(MaxMindServiceError::MissingDatabase);
 		}
 		let file_size = path.metadata()?.len();
 		let archive_file_size = info.path.metadata()?.len();
@@ -133,55 +218,178 @@ impl MaxMindService {
 		}))
 	}
 	
+	/// Reads metadata of standalone `.mmdb` file or `.tar.gz` archive
+	/// containing one.
+	pub fn inspect(path: &Path) -> Result<GeoIpDatabaseMetadata, MaxMindServiceError> {
+		let extracted;
+		let mmdb_path = if path.extension().and_then(OsStr::to_str) == Some("gz") {
+			extracted = tempfile::NamedTempFile::new()?;
+			if !Self::extract_mmdb(path, extracted.path())? {
+				return Err(MaxMindServiceError::MissingDatabase);
+			}
+			extracted.path()
+		} else {
+			path
+		};
+		let file_size = mmdb_path.metadata()?.len();
+		let reader = maxminddb::Reader::open_mmap(mmdb_path)?;
+		let metadata = &reader.metadata;
+		Ok(GeoIpDatabaseMetadata {
+			database_type: metadata.database_type.clone(),
+			timestamp: DateTime::from_timestamp_secs(metadata.build_epoch as i64),
+			binary_format_version: format!(
+				"{}.{}",
+				metadata.binary_format_major_version,
+				metadata.binary_format_minor_version,
+			),
+			ip_version: metadata.ip_version,
+			node_count: metadata.node_count,
+			record_size: metadata.record_size,
+			locales: metadata.languages.clone(),
+			description: metadata.description.clone(),
+			file_size,
+		})
+	}
+	
 	pub fn start_updater(&self) {
-		let me = self.me.upgrade().expect("Unable to upgrade me");
-		if !self.config.auto_update {
+		let editions: Vec<_> = self.config.maxmind_editions
+			.iter()
+			.filter(|edition| self.config.edition_auto_update(edition))
+			.cloned()
+			.collect();
+		if editions.is_empty() {
 			info!("Auto-update is disabled");
 			return;
-		} else {
-			info!("Auto-update interval: {} hour(s)", self.config.auto_update_interval);
 		}
-		tokio::task::spawn(async move {
-			info!("Started background auto-updater");
-			let mut interval = tokio::time::interval(
-				Duration::from_hours(me.config.auto_update_interval),
-			);
-			interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+		for edition in editions {
+			let me = self.me.upgrade().expect("Unable to upgrade me");
+			let hours = self.update_interval(&edition);
+			info!("{edition} auto-update interval: {hours} hour(s)");
+			tokio::task::spawn(async move {
+				info!("Started background auto-updater for {edition}");
+				me.run_updater(&edition, Duration::from_hours(hours)).await;
+			});
+		}
+	}
+	
+	async fn run_updater(&self, edition: &str, period: Duration) {
+		let Some(tracker) = self.updates.get(edition) else { return };
+		let mut interval = tokio::time::interval(period);
+		interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+		loop {
+			interval.tick().await;
+			let started = Utc::now();
+			let next_update = started + period;
+			tracker.schedule(next_update);
+			let mut attempts = 0;
 			loop {
-				interval.tick().await;
-				me.update_all().await;
+				let Err(err) = self.run_update(edition, false).await else {
+					tracker.clear_retry();
+					break;
+				};
+				if !err.is_transient() {
+					tracker.clear_retry();
+					break;
+				}
+				attempts += 1;
+				let delay = self.retry_delay(attempts, err.retry_after());
+				let next_retry = Utc::now() + delay;
+				if next_retry >= next_update {
+					tracker.clear_retry();
+					break;
+				}
+				warn!("Unable to update {edition} (attempt {attempts}), retrying at {next_retry}: {err}");
+				tracker.schedule_retry(attempts, next_retry);
+				tokio::time::sleep(delay).await;
 			}
-		});
+		}
+	}
+	
+	/// Capped exponential backoff with jitter, never shorter than
+	/// delay requested by server.
+	fn retry_delay(&self, attempts: u32, retry_after: Option<Duration>) -> Duration {
+		let initial = self.config.retry_initial_delay;
+		let max = self.config.retry_max_delay;
+		let delay = initial.saturating_mul(1 << attempts.saturating_sub(1).min(32)).min(max);
+		let delay = rand::random_range(delay / 2..=delay);
+		Duration::from_secs(delay).max(retry_after.unwrap_or_default())
 	}
 	
-	async fn update_all(&self) {
-		info!("Updating all databases");
+	/// Updates `edition` bypassing freshness check.
+	pub async fn force_update(&self, edition: &str) -> Result<UpdateResult, MaxMindServiceError> {
+		if !self.updates.contains_key(edition) {
+			return Err(MaxMindServiceError::UnknownEdition);
+		}
+		let res = self.run_update(edition, true).await;
+		Ok(Self::update_result(edition, res))
+	}
+	
+	pub async fn force_update_all(&self) -> Vec<UpdateResult> {
+		let mut out = Vec::new();
 		for edition in &self.config.maxmind_editions {
-			let res = self.update(edition).await;
-			if let Some(out_err) = self.errors.get(edition) {
-				out_err.store(res.err().map(|err| Arc::new(err.to_string())));
-			}
+			let res = self.run_update(edition, true).await;
+			out.push(Self::update_result(edition, res));
 		}
+		out
 	}
 	
-	async fn update(&self, edition: &str) -> Result<(), MaxMindServiceError> {
+	pub fn update_statuses(&self) -> Vec<UpdateStatus> {
+		self.config.maxmind_editions
+			.iter()
+			.filter_map(|edition| self.updates.get(edition))
+			.map(UpdateTracker::status)
+			.collect()
+	}
+	
+	async fn run_update(&self, edition: &str, force: bool) -> Result<bool, MaxMindServiceError> {
+		let res = match self.updates.get(edition) {
+			Some(tracker) => tracker.run(self.update(edition, force)).await,
+			None => Err(MaxMindServiceError::UnknownEdition),
+		};
+		if let Some(out_err) = self.errors.get(edition) {
+			out_err.store(res.as_ref().err().map(|err| Arc::new(err.to_string())));
+		}
+		res
+	}
+	
+	fn update_result(edition: &str, res: Result<bool, MaxMindServiceError>) -> UpdateResult {
+		UpdateResult {
+			target: edition.to_owned(),
+			updated: matches!(res, Ok(true)),
+			error: res.err().map(|err| err.to_string()),
+		}
+	}
+	
+	async fn update(&self, edition: &str, force: bool) -> Result<bool, MaxMindServiceError> {
 		info!("Updating {edition}...");
 		let url = self.make_download_url(edition);
-		let auth = self.make_archive_auth();
+		let checksum_url = self.make_checksum_url(edition);
+		let auth = self.make_archive_auth(edition);
+		let interval = if force {
+			Duration::ZERO
+		} else {
+			Duration::from_hours(self.update_interval(edition))
+		};
 		let info = self.files.refresh_archive(
 			edition,
 			&url,
+			checksum_url.as_deref(),
 			auth,
-			Duration::from_hours(self.config.auto_update_interval),
+			interval,
 		).await?;
-		let Some(info) = info else { return Ok(()) };
-		let reader = Self::load_from_archive(info.clone())?;
+		let Some(info) = info else { return Ok(false) };
+		self.close_pruned_historical_readers();
+		let reader = Self::load_from_archive(info.clone(), true)?;
+		if let Some(pinned) = self.files.get_pinned_version(edition) {
+			info!("{edition} is pinned to version {pinned}, keeping it loaded");
+			return Ok(true);
+		}
 		let out_reader = self.readers
 			.get(edition)
 			.ok_or(MaxMindServiceError::UnknownEdition)?;
 		out_reader.store(Some(reader.clone()));
 		info!("Using {}", reader.path.display());
-		Ok(())
+		Ok(true)
 	}
 	
 	fn extract_mmdb(
@@ -196,7 +404,10 @@ impl MaxMindService {
 			let path = entry.path()?;
 			if path.extension().and_then(OsStr::to_str) == Some("mmdb") {
 				info!("Extracting {}...", path.display());
-				entry.unpack(out_path)?;
+				// interrupted extraction must not leave truncated database behind
+				let tmp_path = out_path.with_added_extension("tmp");
+				entry.unpack(&tmp_path)?;
+				fs::rename(&tmp_path, out_path)?;
 				return Ok(true);
 			}
 		}
@@ -204,13 +415,65 @@ impl MaxMindService {
 	}
 	
 	fn make_download_url(&self, edition: &str) -> String {
-		self.config.maxmind_download_url.replace(
-			DOWNLOAD_URL_EDITION_PLACEHOLDER,
-			edition,
-		)
+		self.config.maxmind_edition_configs.get(edition)
+			.and_then(|c| c.download_url.as_deref())
+			.unwrap_or(&self.config.maxmind_download_url)
+			.replace(DOWNLOAD_URL_EDITION_PLACEHOLDER, edition)
+	}
+	
+	/// Checksum url of `edition`, editions with own download url
+	/// don't inherit global checksum url. Empty url disables verification.
+	fn make_checksum_url(&self, edition: &str) -> Option<String> {
+		let config = self.config.maxmind_edition_configs.get(edition);
+		let url = match config {
+			Some(config) if config.checksum_url.is_some() => config.checksum_url.as_deref(),
+			Some(config) if config.download_url.is_some() => None,
+			_ => Some(self.config.maxmind_checksum_url.as_str()),
+		};
+		url.filter(|url| !url.is_empty())
+			.map(|url| url.replace(DOWNLOAD_URL_EDITION_PLACEHOLDER, edition))
+	}
+	
+	/// Picks download authorization for `edition`: explicit `auth` choice,
+	/// then edition's own credentials, then global credentials. Editions
+	/// with own download url don't inherit global credentials implicitly.
+	fn make_archive_auth(&self, edition: &str) -> ArchiveFileAuth {
+		let Some(config) = self.config.maxmind_edition_configs.get(edition) else {
+			return self.make_global_archive_auth();
+		};
+		let bearer_token = config.bearer_token.as_ref()
+			.or(self.config.maxmind_bearer_token.as_ref());
+		let account_id = config.account_id.as_ref()
+			.or(self.config.maxmind_account_id.as_ref());
+		let licence_key = if config.account_id.is_some() {
+			config.licence_key.clone()
+		} else {
+			self.config.maxmind_license_key.clone()
+		};
+		match config.auth {
+			Some(EditionAuth::None) => ArchiveFileAuth::None,
+			Some(EditionAuth::Bearer) => ArchiveFileAuth::Bearer(
+				bearer_token.cloned().unwrap_or_default(),
+			),
+			Some(EditionAuth::Basic) => ArchiveFileAuth::Basic(
+				account_id.cloned().unwrap_or_default(),
+				licence_key,
+			),
+			None => {
+				if let Some(token) = &config.bearer_token {
+					ArchiveFileAuth::Bearer(token.clone())
+				} else if let Some(username) = &config.account_id {
+					ArchiveFileAuth::Basic(username.clone(), config.licence_key.clone())
+				} else if config.download_url.is_some() {
+					ArchiveFileAuth::None
+				} else {
+					self.make_global_archive_auth()
+				}
+			},
+		}
 	}
 	
-	fn make_archive_auth(&self) -> ArchiveFileAuth {
+	fn make_global_archive_auth(&self) -> ArchiveFileAuth {
 		if let Some(token) = &self.config.maxmind_bearer_token {
 			ArchiveFileAuth::Bearer(token.clone())
 		} else if let Some(username) = &self.config.maxmind_account_id {
@@ -223,6 +486,12 @@ impl MaxMindService {
 		}
 	}
 	
+	fn update_interval(&self, edition: &str) -> u64 {
+		self.config.maxmind_edition_configs.get(edition)
+			.and_then(|c| c.auto_update_interval)
+			.unwrap_or(self.config.auto_update_interval)
+	}
+	
 	pub fn status(&self) -> GeoIpStatus {
 		let databases = self.config.maxmind_editions
 			.iter()
@@ -230,6 +499,7 @@ impl MaxMindService {
 			.collect();
 		GeoIpStatus {
 			databases,
+			timezones: self.timezones.status(),
 		}
 	}
 	
@@ -242,43 +512,292 @@ impl MaxMindService {
 			archive_file_size: None,
 			error: None,
 			last_update_check: None,
+			retry_attempts: None,
+			next_retry: None,
 		};
-		if let Some(reader) = self.readers.get(edition).map(ArcSwapOption::load) {
-			if let Some(reader) = reader.as_ref() {
-				status.timestamp = DateTime::from_timestamp_secs(
-					reader.reader.metadata.build_epoch as i64,
-				);
-				status.locales = reader.reader.metadata.languages.clone();
-				status.file_size = Some(reader.file_size);
-				status.archive_file_size = reader.archive_file_size;
-				status.last_update_check = Some(reader.info.utime);
-			}
+		if let Some(reader) = self.readers.get(edition).map(ArcSwapOption::load)
+			&& let Some(reader) = reader.as_ref()
+		{
+			status.timestamp = DateTime::from_timestamp_secs(
+				reader.reader.metadata.build_epoch as i64,
+			);
+			status.locales = reader.reader.metadata.languages.clone();
+			status.file_size = Some(reader.file_size);
+			status.archive_file_size = reader.archive_file_size;
+			status.last_update_check = Some(reader.info.utime);
 		}
 		status.error = self.errors.get(edition).and_then(|err| {
 			err.load().as_ref().map(|err| (**err).clone())
 		});
+		if let Some(update) = self.updates.get(edition).map(UpdateTracker::status)
+			&& update.retry_attempts > 0
+		{
+			status.retry_attempts = Some(update.retry_attempts);
+			status.next_retry = update.next_retry;
+		}
 		status
 	}
 	
+	/// Looks up `ip` in every listed edition and merges results,
+	/// editions listed first take precedence. Editions without
+	/// loaded database are skipped unless all of them are missing.
+	/// Resulting network is the most specific one among matched
+	/// networks, so merged result is valid for the whole network.
 	pub fn lookup(
 		&self,
 		ip: IpAddr,
 		locale: &str,
-		edition: Option<&str>,
-	) -> Result<Option<GeoIpInfo>, MaxMindServiceError> {
-		let reader = self.get_reader(edition)?;
-		if reader.reader.metadata.database_type == "GeoLite2-ASN" {
-			let res = reader.reader.lookup::<geoip2::Asn>(ip)?;
-			let Some(res) = res else { return Ok(None) };
-			return Ok(Some(GeoIpInfo {
-				autonomous_system_number: res.autonomous_system_number,
-				autonomous_system_organization: res.autonomous_system_organization.map(str::to_owned),
-				..Default::default()
-			}));
-		}
-		let res = reader.reader.lookup::<geoip2::Enterprise>(ip)?;
-		let Some(res) = res else { return Ok(None) };
-		Ok(Some(GeoIpInfo {
+		editions: &[&str],
+		timezone_details: bool,
+	) -> Result<MaxMindLookup, MaxMindServiceError> {
+		let readers = editions.iter()
+			.map(|edition| self.get_reader(Some(edition)))
+			.collect();
+		self.lookup_readers(ip, locale, readers, timezone_details)
+	}
+	
+	/// Same as [`Self::lookup`], but uses database versions selected
+	/// by `selector` instead of loaded ones. Editions without such
+	/// version are skipped unless all of them are missing.
+	pub async fn lookup_historical(
+		&self,
+		ip: IpAddr,
+		locale: &str,
+		editions: &[&str],
+		selector: VersionSelector,
+		timezone_details: bool,
+	) -> Result<MaxMindLookup, MaxMindServiceError> {
+		let mut readers = Vec::with_capacity(editions.len());
+		for edition in editions {
+			readers.push(self.get_historical_reader(edition, selector).await);
+		}
+		self.lookup_readers(ip, locale, readers, timezone_details)
+	}
+	
+	fn lookup_readers(
+		&self,
+		ip: IpAddr,
+		locale: &str,
+		readers: Vec<Result<Arc<MaxMindDbReader>, MaxMindServiceError>>,
+		timezone_details: bool,
+	) -> Result<MaxMindLookup, MaxMindServiceError> {
+		if readers.len() <= 1 {
+			let reader = readers.into_iter()
+				.next()
+				.unwrap_or(Err(MaxMindServiceError::UnknownEdition))?;
+			return self.lookup_edition(ip, locale, &reader, timezone_details);
+		}
+		let mut out: Option<MaxMindLookup> = None;
+		let mut missing = MaxMindServiceError::MissingDatabase;
+		for reader in readers {
+			let res = match reader.and_then(|reader| {
+				self.lookup_edition(ip, locale, &reader, timezone_details)
+			}) {
+				Ok(res) => res,
+				Err(err @ (MaxMindServiceError::MissingDatabase | MaxMindServiceError::UnknownVersion)) => {
+					missing = err;
+					continue;
+				},
+				Err(err) => return Err(err),
+			};
+			let Some(out) = &mut out else {
+				out = Some(res);
+				continue;
+			};
+			if res.network.prefix() > out.network.prefix() {
+				out.network = res.network;
+			}
+			out.versions.extend(res.versions);
+			let Some(info) = res.info else { continue };
+			match &mut out.info {
+				Some(out_info) => out_info.merge(info),
+				None => out.info = Some(info),
+			}
+		}
+		out.ok_or(missing)
+	}
+	
+	fn lookup_edition(
+		&self,
+		ip: IpAddr,
+		locale: &str,
+		reader: &MaxMindDbReader,
+		timezone_details: bool,
+	) -> Result<MaxMindLookup, MaxMindServiceError> {
+		let (info, prefix_len) = self.lookup_reader(&reader.reader, ip, locale, timezone_details)?;
+		let network = IpNetwork::new(ip, prefix_len as u8)?;
+		let network = IpNetwork::new(network.network(), prefix_len as u8)?;
+		Ok(MaxMindLookup {
+			info,
+			network,
+			versions: BTreeMap::from([(reader.info.tag.clone(), reader.info.mtime)]),
+		})
+	}
+	
+	/// Finds retained version of `edition` matching `selector`, opening
+	/// it unless it is the loaded one. Opened versions are kept in LRU
+	/// cache of `HISTORICAL_READERS` size.
+	async fn get_historical_reader(
+		&self,
+		edition: &str,
+		selector: VersionSelector,
+	) -> Result<Arc<MaxMindDbReader>, MaxMindServiceError> {
+		if !self.updates.contains_key(edition) {
+			return Err(MaxMindServiceError::UnknownEdition);
+		}
+		let info = self.files.list_archives(edition)
+			.into_iter()
+			.find(|info| match selector {
+				VersionSelector::At(at) => info.mtime <= at,
+				VersionSelector::Exact(version) => info.mtime == version,
+			})
+			.ok_or(MaxMindServiceError::UnknownVersion)?;
+		if let Ok(reader) = self.get_reader(Some(edition))
+			&& reader.info.path == info.path
+		{
+			return Ok(reader);
+		}
+		if let Some(reader) = self.get_cached_historical_reader(&info) {
+			return Ok(reader);
+		}
+		// Serializes opening, so the same archive isn't extracted twice
+		let _guard = self.historical_open_lock.lock().await;
+		if let Some(reader) = self.get_cached_historical_reader(&info) {
+			return Ok(reader);
+		}
+		info!("Opening {edition} version {}", info.mtime);
+		let extract = !self.files.is_read_only();
+		let reader = tokio::task::spawn_blocking(move || {
+			Self::load_from_archive(info, extract)
+		}).await??;
+		let capacity = self.config.historical_readers;
+		if capacity > 0 {
+			let mut cache = self.historical_readers.lock().expect("Unable to lock historical readers");
+			cache.push_front(reader.clone());
+			cache.truncate(capacity);
+		}
+		Ok(reader)
+	}
+	
+	/// Closes historical readers of versions dropped by retention,
+	/// archives are deleted only once nobody uses them.
+	fn close_pruned_historical_readers(&self) {
+		let mut cache = self.historical_readers.lock().expect("Unable to lock historical readers");
+		cache.retain(|reader| self.files.get_archive(&reader.info.tag, reader.info.mtime).is_some());
+	}
+	
+	fn get_cached_historical_reader(&self, info: &ArchiveFileInfo) -> Option<Arc<MaxMindDbReader>> {
+		let mut cache = self.historical_readers.lock().expect("Unable to lock historical readers");
+		let index = cache.iter().position(|reader| reader.info.path == info.path)?;
+		let reader = cache.remove(index)?;
+		cache.push_front(reader.clone());
+		Some(reader)
+	}
+	
+	fn lookup_reader(
+		&self,
+		reader: &maxminddb::Reader<maxminddb::Mmap>,
+		ip: IpAddr,
+		locale: &str,
+		timezone_details: bool,
+	) -> Result<(Option<GeoIpInfo>, usize), MaxMindServiceError> {
+		match reader.metadata.database_type.as_str() {
+			"GeoLite2-ASN" => Self::lookup_asn(reader, ip),
+			"GeoIP2-Anonymous-IP" => Self::lookup_anonymous_ip(reader, ip),
+			"GeoIP2-Connection-Type" => Self::lookup_connection_type(reader, ip),
+			"GeoIP2-ISP" => Self::lookup_isp(reader, ip),
+			"GeoIP2-Domain" => Self::lookup_domain(reader, ip),
+			_ => self.lookup_enterprise(reader, ip, locale, timezone_details),
+		}
+	}
+	
+	fn lookup_asn(
+		reader: &maxminddb::Reader<maxminddb::Mmap>,
+		ip: IpAddr,
+	) -> Result<(Option<GeoIpInfo>, usize), MaxMindServiceError> {
+		let (res, prefix_len) = reader.lookup_prefix::<geoip2::Asn>(ip)?;
+		let Some(res) = res else { return Ok((None, prefix_len)) };
+		Ok((Some(GeoIpInfo {
+			autonomous_system_number: res.autonomous_system_number,
+			autonomous_system_organization: res.autonomous_system_organization.map(str::to_owned),
+			..Default::default()
+		}), prefix_len))
+	}
+	
+	fn lookup_anonymous_ip(
+		reader: &maxminddb::Reader<maxminddb::Mmap>,
+		ip: IpAddr,
+	) -> Result<(Option<GeoIpInfo>, usize), MaxMindServiceError> {
+		let (res, prefix_len) = reader.lookup_prefix::<geoip2::AnonymousIp>(ip)?;
+		let Some(res) = res else { return Ok((None, prefix_len)) };
+		Ok((Some(GeoIpInfo {
+			is_anonymous: res.is_anonymous,
+			is_anonymous_vpn: res.is_anonymous_vpn,
+			is_hosting_provider: res.is_hosting_provider,
+			is_public_proxy: res.is_public_proxy,
+			is_residential_proxy: res.is_residential_proxy,
+			is_tor_exit_node: res.is_tor_exit_node,
+			..Default::default()
+		}), prefix_len))
+	}
+	
+	fn lookup_connection_type(
+		reader: &maxminddb::Reader<maxminddb::Mmap>,
+		ip: IpAddr,
+	) -> Result<(Option<GeoIpInfo>, usize), MaxMindServiceError> {
+		let (res, prefix_len) = reader.lookup_prefix::<geoip2::ConnectionType>(ip)?;
+		let Some(res) = res else { return Ok((None, prefix_len)) };
+		Ok((Some(GeoIpInfo {
+			connection_type: res.connection_type.map(str::to_owned),
+			..Default::default()
+		}), prefix_len))
+	}
+	
+	fn lookup_isp(
+		reader: &maxminddb::Reader<maxminddb::Mmap>,
+		ip: IpAddr,
+	) -> Result<(Option<GeoIpInfo>, usize), MaxMindServiceError> {
+		let (res, prefix_len) = reader.lookup_prefix::<geoip2::Isp>(ip)?;
+		let Some(res) = res else { return Ok((None, prefix_len)) };
+		Ok((Some(GeoIpInfo {
+			autonomous_system_number: res.autonomous_system_number,
+			autonomous_system_organization: res.autonomous_system_organization.map(str::to_owned),
+			isp: res.isp.map(str::to_owned),
+			organization: res.organization.map(str::to_owned),
+			mobile_country_code: res.mobile_country_code.map(str::to_owned),
+			mobile_network_code: res.mobile_network_code.map(str::to_owned),
+			..Default::default()
+		}), prefix_len))
+	}
+	
+	fn lookup_domain(
+		reader: &maxminddb::Reader<maxminddb::Mmap>,
+		ip: IpAddr,
+	) -> Result<(Option<GeoIpInfo>, usize), MaxMindServiceError> {
+		let (res, prefix_len) = reader.lookup_prefix::<geoip2::Domain>(ip)?;
+		let Some(res) = res else { return Ok((None, prefix_len)) };
+		Ok((Some(GeoIpInfo {
+			domain: res.domain.map(str::to_owned),
+			..Default::default()
+		}), prefix_len))
+	}
+	
+	fn lookup_enterprise(
+		&self,
+		reader: &maxminddb::Reader<maxminddb::Mmap>,
+		ip: IpAddr,
+		locale: &str,
+		timezone_details: bool,
+	) -> Result<(Option<GeoIpInfo>, usize), MaxMindServiceError> {
+		let (res, prefix_len) = reader.lookup_prefix::<geoip2::Enterprise>(ip)?;
+		let Some(res) = res else { return Ok((None, prefix_len)) };
+		let timezone = res.location.as_ref()
+			.and_then(|c| c.time_zone)
+			.and_then(|zone| self.timezones.lookup(zone));
+		let local_time_type = timezone.as_ref()
+			.filter(|_| timezone_details)
+			.map(|zone| zone.local_time_type(Utc::now().timestamp()));
+		Ok((Some(GeoIpInfo {
 			continent_id: res.continent.as_ref().and_then(|c| c.geoname_id),
 			continent_code: res.continent.as_ref().and_then(|c| c.code).map(str::to_owned),
 			continent_name: res.continent
@@ -306,10 +825,16 @@ impl MaxMindService {
 				.map(|c| (*c).to_owned()),
 			metro_code: res.location.as_ref().and_then(|c| c.metro_code),
 			postal_code: res.postal.as_ref().and_then(|c| c.code).map(str::to_owned),
-			timezone: res.location.as_ref().and_then(|c| c.time_zone).map(str::to_owned),
-			posix_timezone: res.location.as_ref()
-				.and_then(|c| c.time_zone)
-				.and_then(|zone| self.timezones.lookup(zone)),
+			timezone: res.location.as_ref().and_then(|c| c.time_zone).map(|zone| {
+				if self.config.canonical_timezones {
+					self.timezones.canonical(zone)
+				} else {
+					zone.to_owned()
+				}
+			}),
+			posix_timezone: timezone.as_ref().map(|zone| zone.posix().to_owned()),
+			utc_offset: local_time_type.map(|local| local.utc_offset),
+			dst: local_time_type.map(|local| local.dst),
 			latitude: res.location.as_ref().and_then(|c| c.latitude),
 			longitude: res.location.as_ref().and_then(|c| c.longitude),
 			accuracy_radius: res.location.as_ref().and_then(|c| c.accuracy_radius),
@@ -321,7 +846,24 @@ impl MaxMindService {
 			autonomous_system_organization: res.traits.as_ref()
 				.and_then(|c| c.autonomous_system_organization)
 				.map(str::to_owned),
-		}))
+			is_anonymous: res.traits.as_ref().and_then(|c| c.is_anonymous),
+			is_anonymous_vpn: res.traits.as_ref().and_then(|c| c.is_anonymous_vpn),
+			is_hosting_provider: res.traits.as_ref().and_then(|c| c.is_hosting_provider),
+			is_public_proxy: res.traits.as_ref().and_then(|c| c.is_public_proxy),
+			is_residential_proxy: res.traits.as_ref().and_then(|c| c.is_residential_proxy),
+			is_tor_exit_node: res.traits.as_ref().and_then(|c| c.is_tor_exit_node),
+			connection_type: res.traits.as_ref().and_then(|c| c.connection_type).map(str::to_owned),
+			isp: res.traits.as_ref().and_then(|c| c.isp).map(str::to_owned),
+			organization: res.traits.as_ref().and_then(|c| c.organization).map(str::to_owned),
+			domain: res.traits.as_ref().and_then(|c| c.domain).map(str::to_owned),
+			mobile_country_code: res.traits.as_ref()
+				.and_then(|c| c.mobile_country_code)
+				.map(str::to_owned),
+			mobile_network_code: res.traits.as_ref()
+				.and_then(|c| c.mobile_network_code)
+				.map(str::to_owned),
+			user_type: res.traits.as_ref().and_then(|c| c.user_type).map(str::to_owned),
+		}), prefix_len))
 	}
 	
 	fn get_reader(
@@ -341,7 +883,165 @@ impl MaxMindService {
 		Ok(self.get_reader(Some(edition))?.info.clone())
 	}
 	
+	/// Lists retained versions of `edition`, the newest first.
+	pub fn list_versions(&self, edition: &str) -> Result<EditionVersions, MaxMindServiceError> {
+		if !self.updates.contains_key(edition) {
+			return Err(MaxMindServiceError::UnknownEdition);
+		}
+		let pinned = self.files.get_pinned_version(edition);
+		let active = self.get_archive(edition).ok().map(|info| info.mtime);
+		let versions = self.files.list_archives(edition)
+			.into_iter()
+			.map(|info| ArchiveVersion {
+				timestamp: info.mtime,
+				archive_file_size: info.path.metadata().ok().map(|m| m.len()),
+				pinned: pinned == Some(info.mtime),
+				active: active == Some(info.mtime),
+			})
+			.collect();
+		Ok(EditionVersions {
+			edition: edition.to_owned(),
+			pinned,
+			versions,
+		})
+	}
+	
+	/// Pins `edition` to retained version `timestamp` (currently loaded
+	/// version by default) and loads it. Pinned version stays loaded
+	/// until unpinned, newer versions are still downloaded and retained.
+	pub async fn pin_version(
+		&self,
+		edition: &str,
+		timestamp: Option<DateTime<Utc>>,
+	) -> Result<EditionVersions, MaxMindServiceError> {
+		let info = match timestamp {
+			Some(timestamp) => self.files.get_archive(edition, timestamp)
+				.ok_or(MaxMindServiceError::UnknownVersion)?,
+			None => self.get_archive(edition)?,
+		};
+		self.load_version(edition, info.clone())?;
+		self.files.set_pinned_version(edition, Some(info.mtime)).await?;
+		self.close_pruned_historical_readers();
+		info!("{edition} pinned to version {}", info.mtime);
+		self.list_versions(edition)
+	}
+	
+	/// Unpins `edition` and loads its latest version.
+	pub async fn unpin_version(&self, edition: &str) -> Result<EditionVersions, MaxMindServiceError> {
+		if !self.updates.contains_key(edition) {
+			return Err(MaxMindServiceError::UnknownEdition);
+		}
+		self.files.set_pinned_version(edition, None).await?;
+		self.close_pruned_historical_readers();
+		info!("{edition} unpinned");
+		if let Some(info) = self.files.get_latest_archive(edition)
+			&& self.get_archive(edition).ok().is_none_or(|active| active.mtime != info.mtime)
+		{
+			self.load_version(edition, info)?;
+		}
+		self.list_versions(edition)
+	}
+	
+	/// Only stores pin of `edition` to retained version `timestamp` (version
+	/// server uses by default), nothing is loaded or deleted, so that it can be
+	/// done next to running server, which picks it up on restart.
+	pub async fn store_pin(
+		&self,
+		edition: &str,
+		timestamp: Option<DateTime<Utc>>,
+	) -> Result<EditionVersions, MaxMindServiceError> {
+		if !self.updates.contains_key(edition) {
+			return Err(MaxMindServiceError::UnknownEdition);
+		}
+		let info = match timestamp {
+			Some(timestamp) => self.files.get_archive(edition, timestamp),
+			None => self.files.get_current_archive(edition),
+		}.ok_or(MaxMindServiceError::UnknownVersion)?;
+		self.files.write_pin(edition, Some(info.mtime)).await?;
+		self.list_versions(edition)
+	}
+	
+	/// Only removes pin of `edition`, see [`Self::store_pin`].
+	pub async fn remove_pin(&self, edition: &str) -> Result<EditionVersions, MaxMindServiceError> {
+		if !self.updates.contains_key(edition) {
+			return Err(MaxMindServiceError::UnknownEdition);
+		}
+		self.files.write_pin(edition, None).await?;
+		self.list_versions(edition)
+	}
+	
+	fn load_version(
+		&self,
+		edition: &str,
+		info: Arc<ArchiveFileInfo>,
+	) -> Result<(), MaxMindServiceError> {
+		let reader = Self::load_from_archive(info, !self.files.is_read_only())?;
+		let out_reader = self.readers
+			.get(edition)
+			.ok_or(MaxMindServiceError::UnknownEdition)?;
+		out_reader.store(Some(reader.clone()));
+		if let Some(out_err) = self.errors.get(edition) {
+			out_err.store(None);
+		}
+		info!("Using {}", reader.path.display());
+		Ok(())
+	}
+	
 	pub fn default_edition(&self) -> Option<&str> {
 		self.config.maxmind_editions.first().map(String::as_str)
 	}
+	
+	/// Checks that all `editions` are known and at least one of them
+	/// has database loaded, so lookups in them fail only per address.
+	pub fn check_editions(&self, editions: &[&str]) -> Result<(), MaxMindServiceError> {
+		if editions.is_empty() {
+			return Err(MaxMindServiceError::UnknownEdition);
+		}
+		let mut loaded = false;
+		for edition in editions {
+			match self.get_reader(Some(edition)) {
+				Ok(_) => loaded = true,
+				Err(MaxMindServiceError::MissingDatabase) => {},
+				Err(err) => return Err(err),
+			}
+		}
+		if !loaded {
+			return Err(MaxMindServiceError::MissingDatabase);
+		}
+		Ok(())
+	}
+	
+	/// Picks editions for lookup: explicit `editions` list,
+	/// then single `edition`, then configured merge list
+	/// and finally the default edition. Duplicates are dropped
+	/// and unknown editions rejected, so the list is never longer
+	/// than the configured one.
+	pub fn resolve_editions<'a>(
+		&'a self,
+		edition: Option<&'a str>,
+		editions: Vec<&'a str>,
+	) -> Result<Vec<&'a str>, MaxMindServiceError> {
+		let editions = if !editions.is_empty() {
+			editions
+		} else if let Some(edition) = edition {
+			vec![edition]
+		} else if !self.config.maxmind_merge_editions.is_empty() {
+			self.config.maxmind_merge_editions
+				.iter()
+				.map(String::as_str)
+				.collect()
+		} else {
+			self.default_edition().into_iter().collect()
+		};
+		let mut out = Vec::with_capacity(editions.len().min(self.readers.len()));
+		for edition in editions {
+			if !self.readers.contains_key(edition) {
+				return Err(MaxMindServiceError::UnknownEdition);
+			}
+			if !out.contains(&edition) {
+				out.push(edition);
+			}
+		}
+		Ok(out)
+	}
 }
@@ -3,12 +3,16 @@ mod templates;
 mod recaptcha;
 mod timezones;
 mod files;
+mod updates;
+mod rate_limits;
 
 pub use maxmind::*;
 pub use templates::*;
 pub use recaptcha::*;
 pub use timezones::*;
 pub use files::*;
+pub use updates::*;
+pub use rate_limits::*;
 
 use std::sync::Arc;
 use reqwest::Client;
@@ -22,6 +26,7 @@ pub struct AppState {
 	pub templates: Arc<TemplateService>,
 	pub recaptcha: Arc<RecaptchaService>,
 	pub files: Arc<FileService>,
+	pub rate_limits: Arc<RateLimitService>,
 }
 
 impl AppState {
@@ -36,6 +41,7 @@ impl AppState {
 		).await;
 		let templates = TemplateService::new(config.clone());
 		let recaptcha = RecaptchaService::new(config.clone(), client.clone());
+		let rate_limits = RateLimitService::new(config.clone());
 		
 		Arc::new(Self {
 			config,
@@ -45,6 +51,7 @@ impl AppState {
 			templates,
 			recaptcha,
 			files,
+			rate_limits,
 		})
 	}
 }
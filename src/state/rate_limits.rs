@@ -0,0 +1,260 @@
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use ahash::AHashMap;
+use crate::config::{ApiKey, ApiScope, AppConfig, RateLimit};
+
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Who is rate limited: API key or client address of anonymous request.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RateLimitSubject {
+	Key(Arc<str>),
+	Ip(IpAddr),
+}
+
+/// Bucket state after consumed (or rejected) request.
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimitStatus {
+	pub limit: u32,
+	pub remaining: u32,
+	/// Time until bucket is full again.
+	pub reset: Duration,
+	/// Time until next request is allowed, set if request is rejected.
+	pub retry_after: Option<Duration>,
+}
+
+struct TokenBucket {
+	tokens: f64,
+	updated: Instant,
+}
+
+struct Buckets {
+	buckets: AHashMap<(ApiScope, RateLimitSubject), TokenBucket>,
+	last_cleanup: Instant,
+}
+
+pub struct RateLimitService {
+	config: Arc<AppConfig>,
+	buckets: Mutex<Buckets>,
+}
+
+impl RateLimitService {
+	pub fn new(config: Arc<AppConfig>) -> Arc<Self> {
+		Arc::new(Self {
+			config,
+			buckets: Mutex::new(Buckets {
+				buckets: AHashMap::new(),
+				last_cleanup: Instant::now(),
+			}),
+		})
+	}
+	
+	/// Consumes `cost` tokens from bucket of `subject` in route group `scope`,
+	/// request is rejected (and nothing consumed) if bucket doesn't have
+	/// that many. Returns `None` if group isn't rate limited.
+	pub fn check(
+		&self,
+		scope: ApiScope,
+		key: Option<&ApiKey>,
+		subject: RateLimitSubject,
+		cost: u32,
+	) -> Option<RateLimitStatus> {
+		let rate_limit = key
+			.and_then(|key| key.rate_limits.get(&scope))
+			.or_else(|| self.config.rate_limits.get(&scope))?;
+		let subject = match subject {
+			RateLimitSubject::Ip(ip) => {
+				RateLimitSubject::Ip(ip_bucket(ip, self.config.rate_limit_ipv6_prefix))
+			},
+			subject => subject,
+		};
+		let now = Instant::now();
+		let mut buckets = self.buckets.lock().expect("Unable to lock rate limit buckets");
+		if now.duration_since(buckets.last_cleanup) >= CLEANUP_INTERVAL {
+			self.cleanup(&mut buckets, now);
+		}
+		let bucket = buckets.buckets.entry((scope, subject)).or_insert(TokenBucket {
+			tokens: rate_limit.requests as f64,
+			updated: now,
+		});
+		Self::refill(bucket, rate_limit, now);
+		let rate = Self::refill_rate(rate_limit);
+		let cost = cost as f64;
+		let retry_after = if bucket.tokens >= cost {
+			bucket.tokens -= cost;
+			None
+		} else {
+			Some(Duration::from_secs_f64((cost - bucket.tokens) / rate))
+		};
+		Some(RateLimitStatus {
+			limit: rate_limit.requests,
+			remaining: bucket.tokens as u32,
+			reset: Duration::from_secs_f64((rate_limit.requests as f64 - bucket.tokens) / rate),
+			retry_after,
+		})
+	}
+	
+	fn refill(bucket: &mut TokenBucket, rate_limit: &RateLimit, now: Instant) {
+		let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+		bucket.tokens = (bucket.tokens + elapsed * Self::refill_rate(rate_limit))
+			.min(rate_limit.requests as f64);
+		bucket.updated = now;
+	}
+	
+	fn refill_rate(rate_limit: &RateLimit) -> f64 {
+		rate_limit.requests as f64 / rate_limit.period as f64
+	}
+	
+	/// Drops buckets which are full by now, they are
+	/// indistinguishable from freshly created ones.
+	fn cleanup(&self, buckets: &mut Buckets, now: Instant) {
+		buckets.buckets.retain(|(scope, subject), bucket| {
+			let rate_limit = self.config.api_keys.iter()
+				.find(|key| matches!(subject, RateLimitSubject::Key(name) if *name == key.name))
+				.and_then(|key| key.rate_limits.get(scope))
+				.or_else(|| self.config.rate_limits.get(scope));
+			let Some(rate_limit) = rate_limit else { return false };
+			Self::refill(bucket, rate_limit, now);
+			bucket.tokens < rate_limit.requests as f64
+		});
+		buckets.last_cleanup = now;
+	}
+}
+
+/// Address identifying bucket of client, IPv6 addresses are reduced
+/// to network of `ipv6_prefix` bits.
+fn ip_bucket(ip: IpAddr, ipv6_prefix: u8) -> IpAddr {
+	match ip.to_canonical() {
+		IpAddr::V6(ip) => {
+			let mask = u128::MAX.checked_shl(128 - u32::from(ipv6_prefix.min(128))).unwrap_or(0);
+			IpAddr::V6(Ipv6Addr::from(u128::from(ip) & mask))
+		},
+		ip => ip,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn service() -> Arc<RateLimitService> {
+		let config = AppConfig::from_toml(r#"
+			data_dir = "/nonexistent"
+			
+			[rate_limits]
+			"geoip:lookup" = "2/60"
+			
+			[api_keys.mirror]
+			key = "secret"
+			scopes = ["geoip:lookup"]
+			rate_limits = { "geoip:lookup" = "5/60" }
+		"#).expect("Invalid config");
+		RateLimitService::new(config)
+	}
+	
+	fn ip(ip: &str) -> RateLimitSubject {
+		RateLimitSubject::Ip(ip.parse().expect("Invalid address"))
+	}
+	
+	#[test]
+	fn rejects_when_bucket_is_empty() {
+		let service = service();
+		let check = || service.check(ApiScope::GeoIpLookup, None, ip("192.0.2.1"), 1)
+			.expect("Scope is not rate limited");
+		let first = check();
+		assert_eq!((first.limit, first.remaining, first.retry_after), (2, 1, None));
+		assert!(first.reset <= Duration::from_secs(30));
+		assert_eq!(check().remaining, 0);
+		let rejected = check();
+		assert_eq!(rejected.remaining, 0);
+		let retry_after = rejected.retry_after.expect("Request was not rejected");
+		assert!(retry_after > Duration::from_secs(29) && retry_after <= Duration::from_secs(30));
+		assert!(rejected.reset > Duration::from_secs(59) && rejected.reset <= Duration::from_secs(60));
+		// Other clients have their own buckets
+		let other = service.check(ApiScope::GeoIpLookup, None, ip("192.0.2.2"), 1);
+		assert_eq!(other.expect("Scope is not rate limited").remaining, 1);
+	}
+	
+	#[test]
+	fn consumes_cost_only_if_bucket_covers_it() {
+		let service = service();
+		let check = |cost| service.check(ApiScope::GeoIpLookup, None, ip("192.0.2.1"), cost)
+			.expect("Scope is not rate limited");
+		let rejected = check(3);
+		assert_eq!(rejected.remaining, 2);
+		let retry_after = rejected.retry_after.expect("Request was not rejected");
+		assert!(retry_after > Duration::from_secs(29) && retry_after <= Duration::from_secs(30));
+		let accepted = check(2);
+		assert_eq!((accepted.remaining, accepted.retry_after), (0, None));
+		assert!(check(1).retry_after.is_some());
+	}
+	
+	#[test]
+	fn uses_key_limits_and_skips_unlimited_scopes() {
+		let service = service();
+		let key = service.config.api_keys.first().expect("Missing API key");
+		let subject = RateLimitSubject::Key(key.name.clone());
+		let status = service.check(ApiScope::GeoIpLookup, Some(key), subject.clone(), 1)
+			.expect("Scope is not rate limited");
+		assert_eq!((status.limit, status.remaining), (5, 4));
+		assert!(service.check(ApiScope::Admin, Some(key), subject, 1).is_none());
+		assert!(service.check(ApiScope::Timezones, None, ip("192.0.2.1"), 1).is_none());
+	}
+	
+	#[test]
+	fn shares_bucket_of_ipv6_network() {
+		let service = service();
+		let check = |subject| service.check(ApiScope::GeoIpLookup, None, subject, 1)
+			.expect("Scope is not rate limited")
+			.remaining;
+		assert_eq!(check(ip("2001:db8:0:1::1")), 1);
+		assert_eq!(check(ip("2001:db8:0:1:ffff::2")), 0);
+		assert_eq!(check(ip("2001:db8:0:2::1")), 1);
+		// IPv4-mapped addresses share bucket with IPv4 ones
+		assert_eq!(check(ip("192.0.2.1")), 1);
+		assert_eq!(check(ip("::ffff:192.0.2.1")), 0);
+	}
+	
+	#[test]
+	fn refills_bucket_over_time() {
+		let rate_limit = RateLimit { requests: 2, period: 60 };
+		let start = Instant::now();
+		let mut bucket = TokenBucket { tokens: 0.0, updated: start };
+		RateLimitService::refill(&mut bucket, &rate_limit, start + Duration::from_secs(15));
+		assert_eq!(bucket.tokens, 0.5);
+		RateLimitService::refill(&mut bucket, &rate_limit, start + Duration::from_secs(45));
+		assert_eq!(bucket.tokens, 1.5);
+		// Never more than bucket capacity
+		RateLimitService::refill(&mut bucket, &rate_limit, start + Duration::from_secs(3600));
+		assert_eq!(bucket.tokens, 2.0);
+		assert_eq!(bucket.updated, start + Duration::from_secs(3600));
+	}
+	
+	#[test]
+	fn cleans_up_full_buckets() {
+		let service = service();
+		service.check(ApiScope::GeoIpLookup, None, ip("192.0.2.1"), 1);
+		service.check(ApiScope::GeoIpLookup, None, ip("192.0.2.2"), 1);
+		service.check(ApiScope::GeoIpLookup, None, ip("192.0.2.2"), 1);
+		let mut buckets = service.buckets.lock().unwrap();
+		let now = Instant::now();
+		// The first bucket is full after 30 seconds, the second one isn't
+		service.cleanup(&mut buckets, now + Duration::from_secs(40));
+		assert_eq!(buckets.buckets.len(), 1);
+		service.cleanup(&mut buckets, now + Duration::from_secs(60));
+		assert!(buckets.buckets.is_empty());
+	}
+	
+	#[test]
+	fn masks_ipv6_to_prefix() {
+		let bucket = |addr: &str, prefix| ip_bucket(addr.parse().expect("Invalid address"), prefix)
+			.to_string();
+		assert_eq!(bucket("2001:db8:1:2:3:4:5:6", 64), "2001:db8:1:2::");
+		assert_eq!(bucket("2001:db8:1:2:3:4:5:6", 48), "2001:db8:1::");
+		assert_eq!(bucket("2001:db8:1:2:3:4:5:6", 128), "2001:db8:1:2:3:4:5:6");
+		assert_eq!(bucket("2001:db8:1:2:3:4:5:6", 0), "::");
+		assert_eq!(bucket("::ffff:192.0.2.1", 64), "192.0.2.1");
+		assert_eq!(bucket("192.0.2.1", 0), "192.0.2.1");
+	}
+}
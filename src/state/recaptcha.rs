@@ -1,11 +1,23 @@
-use std::sync::Arc;
-use axum::http::StatusCode;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use ahash::AHashMap;
+use axum::http::{HeaderMap, HeaderValue, StatusCode, header};
+use constant_time_eq::constant_time_eq;
+use hmac::{Hmac, Mac};
 use log::debug;
+use sha2::{Digest, Sha256};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use thiserror::Error;
-use crate::config::AppConfig;
-use crate::model::{RecaptchaVerifyRequest, RecaptchaVerifyResponse};
+use crate::config::{AppConfig, CaptchaProvider};
+use crate::model::{
+	HCaptchaVerifyRequest, HCaptchaVerifyResponse, RecaptchaVerifyRequest,
+	RecaptchaVerifyResponse, TurnstileVerifyRequest, TurnstileVerifyResponse,
+};
 
-const VERIFY_URL: &str = "https://www.google.com/recaptcha/api/siteverify";
+const CAPTCHA_SESSION_COOKIE: &str = "captcha_session";
 
 #[derive(Debug, Error)]
 pub enum RecaptchaServiceError {
@@ -16,48 +28,503 @@ pub enum RecaptchaServiceError {
 	HttpError(StatusCode),
 }
 
+/// Provider-independent part of verify response.
+pub struct CaptchaVerdict {
+	pub success: bool,
+	/// Set only by providers scoring tokens.
+	pub score: Option<f32>,
+	pub action: Option<String>,
+	pub hostname: Option<String>,
+}
+
+pub trait CaptchaVerifier {
+	const VERIFY_URL: &'static str;
+	/// Whether provider scores tokens.
+	const HAS_SCORE: bool;
+	/// Whether provider reports action token was issued for.
+	const HAS_ACTION: bool;
+	type Request: Serialize;
+	type Response: DeserializeOwned;
+	
+	fn make_request(
+		secret: String,
+		response: String,
+		remote_ip: Option<String>,
+		site_key: Option<String>,
+	) -> Self::Request;
+	
+	fn into_verdict(res: Self::Response) -> CaptchaVerdict;
+}
+
+pub struct RecaptchaVerifier;
+
+impl CaptchaVerifier for RecaptchaVerifier {
+	const VERIFY_URL: &'static str = "https://www.google.com/recaptcha/api/siteverify";
+	const HAS_SCORE: bool = true;
+	const HAS_ACTION: bool = true;
+	type Request = RecaptchaVerifyRequest;
+	type Response = RecaptchaVerifyResponse;
+	
+	fn make_request(
+		secret: String,
+		response: String,
+		remote_ip: Option<String>,
+		_site_key: Option<String>,
+	) -> Self::Request {
+		RecaptchaVerifyRequest { secret, response, remote_ip }
+	}
+	
+	fn into_verdict(res: Self::Response) -> CaptchaVerdict {
+		debug!(
+			"Verify response: success={}, score={:?}, action={:?}, \
+			challenge_ts={:?}, hostname={:?}, apk_package_name={:?}, \
+			error_codes={:?}",
+			res.success,
+			res.score,
+			res.action,
+			res.challenge_ts,
+			res.hostname,
+			res.apk_package_name,
+			res.error_codes,
+		);
+		CaptchaVerdict {
+			success: res.success,
+			score: res.score,
+			action: res.action,
+			hostname: res.hostname,
+		}
+	}
+}
+
+pub struct TurnstileVerifier;
+
+impl CaptchaVerifier for TurnstileVerifier {
+	const VERIFY_URL: &'static str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+	const HAS_SCORE: bool = false;
+	const HAS_ACTION: bool = true;
+	type Request = TurnstileVerifyRequest;
+	type Response = TurnstileVerifyResponse;
+	
+	fn make_request(
+		secret: String,
+		response: String,
+		remote_ip: Option<String>,
+		_site_key: Option<String>,
+	) -> Self::Request {
+		TurnstileVerifyRequest { secret, response, remote_ip }
+	}
+	
+	fn into_verdict(res: Self::Response) -> CaptchaVerdict {
+		debug!(
+			"Verify response: success={}, action={:?}, challenge_ts={:?}, \
+			hostname={:?}, cdata={:?}, error_codes={:?}",
+			res.success,
+			res.action,
+			res.challenge_ts,
+			res.hostname,
+			res.cdata,
+			res.error_codes,
+		);
+		CaptchaVerdict {
+			success: res.success,
+			score: None,
+			action: res.action,
+			hostname: res.hostname,
+		}
+	}
+}
+
+pub struct HCaptchaVerifier;
+
+impl CaptchaVerifier for HCaptchaVerifier {
+	const VERIFY_URL: &'static str = "https://api.hcaptcha.com/siteverify";
+	const HAS_SCORE: bool = false;
+	const HAS_ACTION: bool = false;
+	type Request = HCaptchaVerifyRequest;
+	type Response = HCaptchaVerifyResponse;
+	
+	fn make_request(
+		secret: String,
+		response: String,
+		remote_ip: Option<String>,
+		site_key: Option<String>,
+	) -> Self::Request {
+		HCaptchaVerifyRequest { secret, response, remote_ip, site_key }
+	}
+	
+	fn into_verdict(res: Self::Response) -> CaptchaVerdict {
+		debug!(
+			"Verify response: success={}, challenge_ts={:?}, hostname={:?}, \
+			credit={:?}, error_codes={:?}",
+			res.success,
+			res.challenge_ts,
+			res.hostname,
+			res.credit,
+			res.error_codes,
+		);
+		CaptchaVerdict {
+			success: res.success,
+			score: None,
+			action: None,
+			hostname: res.hostname,
+		}
+	}
+}
+
+struct CachedVerdict {
+	valid: bool,
+	expires: Instant,
+}
+
+/// Verification results by hash of token and client address. All entries
+/// live for the same TTL, so insertion order is also expiration order.
+#[derive(Default)]
+struct VerdictCache {
+	verdicts: AHashMap<[u8; 32], CachedVerdict>,
+	order: VecDeque<([u8; 32], Instant)>,
+}
+
+impl VerdictCache {
+	fn get(&self, key: &[u8; 32], now: Instant) -> Option<bool> {
+		self.verdicts.get(key)
+			.filter(|verdict| verdict.expires > now)
+			.map(|verdict| verdict.valid)
+	}
+	
+	/// Inserts verdict evicting expired entries and, if still full,
+	/// the ones closest to expiration.
+	fn insert(&mut self, key: [u8; 32], verdict: CachedVerdict, capacity: usize, now: Instant) {
+		while let Some((oldest, expires)) = self.order.front().copied() {
+			if expires > now && self.verdicts.len() < capacity {
+				break;
+			}
+			self.order.pop_front();
+			// Skip stale entry of key inserted again later
+			if self.verdicts.get(&oldest).is_some_and(|verdict| verdict.expires == expires) {
+				self.verdicts.remove(&oldest);
+			}
+		}
+		self.order.push_back((key, verdict.expires));
+		self.verdicts.insert(key, verdict);
+	}
+}
+
 pub struct RecaptchaService {
 	config: Arc<AppConfig>,
 	client: reqwest::Client,
+	cache: Mutex<VerdictCache>,
+	session_secret: Vec<u8>,
 }
 
 impl RecaptchaService {
 	pub fn new(config: Arc<AppConfig>, client: reqwest::Client) -> Arc<Self> {
+		let session_secret = match &config.captcha_session_secret {
+			Some(secret) => secret.as_bytes().to_vec(),
+			None => rand::random::<[u8; 32]>().to_vec(),
+		};
 		Arc::new(Self {
 			config,
 			client,
+			cache: Mutex::new(VerdictCache::default()),
+			session_secret,
 		})
 	}
 	
-	pub async fn verify(&self, token: &str) -> Result<bool, RecaptchaServiceError> {
-		let Some(secret_key) = self.config.recaptcha_secret_key.as_deref() else {
+	/// Verifies token issued to client at `remote_ip` by configured captcha
+	/// provider, token must have minimum score (if provider scores tokens),
+	/// expected action (if provider reports it) and allowed hostname.
+	/// Results are cached for `CAPTCHA_CACHE_TTL` seconds, so token reused
+	/// by the same client doesn't cost another siteverify call.
+	pub async fn verify(
+		&self,
+		token: &str,
+		remote_ip: Option<IpAddr>,
+	) -> Result<bool, RecaptchaServiceError> {
+		let cache_key = Self::cache_key(token, remote_ip);
+		if let Some(valid) = self.get_cached(&cache_key) {
+			debug!("Using cached verification result: {valid}");
+			return Ok(valid);
+		}
+		let valid = self.verify_uncached(token, remote_ip).await?;
+		self.put_cached(cache_key, valid);
+		Ok(valid)
+	}
+	
+	async fn verify_uncached(
+		&self,
+		token: &str,
+		remote_ip: Option<IpAddr>,
+	) -> Result<bool, RecaptchaServiceError> {
+		match self.config.captcha_provider {
+			CaptchaProvider::Recaptcha => self.verify_with::<RecaptchaVerifier>(token, remote_ip).await,
+			CaptchaProvider::Turnstile => self.verify_with::<TurnstileVerifier>(token, remote_ip).await,
+			CaptchaProvider::HCaptcha => self.verify_with::<HCaptchaVerifier>(token, remote_ip).await,
+		}
+	}
+	
+	async fn verify_with<V: CaptchaVerifier>(
+		&self,
+		token: &str,
+		remote_ip: Option<IpAddr>,
+	) -> Result<bool, RecaptchaServiceError> {
+		let Some(secret_key) = self.config.captcha_secret_key.as_deref() else {
 			return Ok(false);
 		};
-		let params = RecaptchaVerifyRequest {
-			secret: secret_key.to_owned(),
-			response: token.to_owned(),
-			remote_ip: None,
-		};
-		let res = self.client.post(VERIFY_URL)
+		let params = V::make_request(
+			secret_key.to_owned(),
+			token.to_owned(),
+			remote_ip.map(|ip| ip.to_string()),
+			self.config.captcha_site_key.clone(),
+		);
+		let res = self.client.post(V::VERIFY_URL)
 			.form(&params)
 			.send()
 			.await?;
 		if !res.status().is_success() {
 			return Err(RecaptchaServiceError::HttpError(res.status()));
 		}
-		let res: RecaptchaVerifyResponse = res.json().await?;
-		debug!(
-			"Verify response: success={}, score={:?}, action={:?}, \
-			challenge_ts={:?}, hostname={:?}, apk_package_name={:?}, \
-			error_codes={:?}",
-			res.success,
-			res.score,
-			res.action,
-			res.challenge_ts,
-			res.hostname,
-			res.apk_package_name,
-			res.error_codes,
+		let res: V::Response = res.json().await?;
+		let verdict = V::into_verdict(res);
+		if !verdict.success {
+			return Ok(false);
+		}
+		// Tokens without score or action (e.g. reCAPTCHA v2) are checked
+		// only if expected values are configured explicitly
+		if V::HAS_SCORE {
+			let min_score = self.config.captcha_min_score();
+			match verdict.score {
+				Some(score) if score < min_score => {
+					debug!("Rejecting token: score {score} is below {min_score}");
+					return Ok(false);
+				},
+				None if self.config.captcha_min_score.is_some() => {
+					debug!("Rejecting token: missing score");
+					return Ok(false);
+				},
+				_ => {},
+			}
+		}
+		let action = self.config.captcha_action();
+		if V::HAS_ACTION && !action.is_empty() {
+			let rejected = match &verdict.action {
+				Some(verdict_action) => verdict_action != action,
+				None => self.config.captcha_action.is_some(),
+			};
+			if rejected {
+				debug!("Rejecting token: unexpected action {:?}", verdict.action);
+				return Ok(false);
+			}
+		}
+		if !self.config.captcha_hostnames.is_empty()
+			&& !verdict.hostname.as_ref().is_some_and(|h| self.config.captcha_hostnames.contains(h))
+		{
+			debug!("Rejecting token: unexpected hostname {:?}", verdict.hostname);
+			return Ok(false);
+		}
+		Ok(true)
+	}
+	
+	fn cache_key(token: &str, remote_ip: Option<IpAddr>) -> [u8; 32] {
+		let mut hasher = Sha256::new();
+		hasher.update(token.as_bytes());
+		if let Some(ip) = remote_ip {
+			hasher.update(b"|");
+			hasher.update(ip.to_string().as_bytes());
+		}
+		hasher.finalize().into()
+	}
+	
+	fn get_cached(&self, key: &[u8; 32]) -> Option<bool> {
+		let cache = self.cache.lock().expect("Unable to lock captcha cache");
+		cache.get(key, Instant::now())
+	}
+	
+	fn put_cached(&self, key: [u8; 32], valid: bool) {
+		if self.config.captcha_cache_ttl == 0 || self.config.captcha_cache_size == 0 {
+			return;
+		}
+		let now = Instant::now();
+		let verdict = CachedVerdict {
+			valid,
+			expires: now + Duration::from_secs(self.config.captcha_cache_ttl),
+		};
+		let mut cache = self.cache.lock().expect("Unable to lock captcha cache");
+		cache.insert(key, verdict, self.config.captcha_cache_size, now);
+	}
+	
+	/// Issues `Set-Cookie` value with session bound to `remote_ip`,
+	/// returns `None` if sessions are disabled.
+	pub fn issue_session(&self, remote_ip: IpAddr) -> Option<HeaderValue> {
+		let ttl = self.config.captcha_session_ttl;
+		if ttl == 0 {
+			return None;
+		}
+		let expires = unix_time() + ttl;
+		let signature = self.sign_session(expires, remote_ip);
+		let mut cookie = format!(
+			"{CAPTCHA_SESSION_COOKIE}={expires}.{signature}; Max-Age={ttl}; Path=/api; HttpOnly; SameSite=Strict",
 		);
-		Ok(res.success)
+		if self.config.captcha_session_secure {
+			cookie.push_str("; Secure");
+		}
+		HeaderValue::from_str(&cookie).ok()
+	}
+	
+	/// Checks whether request carries unexpired session
+	/// cookie issued to `remote_ip`.
+	pub fn verify_session(&self, headers: &HeaderMap, remote_ip: IpAddr) -> bool {
+		if self.config.captcha_session_ttl == 0 {
+			return false;
+		}
+		let value = headers.get_all(header::COOKIE)
+			.iter()
+			.filter_map(|v| v.to_str().ok())
+			.flat_map(|v| v.split(';'))
+			.filter_map(|cookie| cookie.trim().split_once('='))
+			.find(|(name, _)| *name == CAPTCHA_SESSION_COOKIE)
+			.map(|(_, value)| value);
+		let Some((expires, signature)) = value.and_then(|v| v.split_once('.')) else {
+			return false;
+		};
+		let Ok(expires) = expires.parse::<u64>() else {
+			return false;
+		};
+		expires > unix_time()
+			&& constant_time_eq(
+				self.sign_session(expires, remote_ip).as_bytes(),
+				signature.as_bytes(),
+			)
+	}
+	
+	fn sign_session(&self, expires: u64, remote_ip: IpAddr) -> String {
+		let mut mac = Hmac::<Sha256>::new_from_slice(&self.session_secret)
+			.expect("HMAC accepts keys of any length");
+		mac.update(format!("{expires}|{remote_ip}").as_bytes());
+		format!("{:x}", mac.finalize().into_bytes())
+	}
+}
+
+fn unix_time() -> u64 {
+	SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	fn verdict(valid: bool, expires: Instant) -> CachedVerdict {
+		CachedVerdict { valid, expires }
+	}
+	
+	fn session_service() -> Arc<RecaptchaService> {
+		let config = AppConfig::from_toml(r#"
+			data_dir = "/nonexistent"
+			captcha_session_ttl = 60
+			captcha_session_secret = "0123456789abcdef0123456789abcdef"
+		"#).unwrap();
+		RecaptchaService::new(config, reqwest::Client::new())
+	}
+	
+	fn cookie_headers(cookie: &str) -> HeaderMap {
+		let mut headers = HeaderMap::new();
+		headers.insert(header::COOKIE, HeaderValue::from_str(cookie).unwrap());
+		headers
+	}
+	
+	fn issued_cookie(service: &RecaptchaService, remote_ip: IpAddr) -> String {
+		let value = service.issue_session(remote_ip).unwrap();
+		let value = value.to_str().unwrap();
+		value.split(';').next().unwrap().to_owned()
+	}
+	
+	#[test]
+	fn session_accepted_from_same_ip() {
+		let service = session_service();
+		let ip: IpAddr = "192.0.2.1".parse().unwrap();
+		let cookie = issued_cookie(&service, ip);
+		assert!(service.verify_session(&cookie_headers(&cookie), ip));
+		assert!(service.verify_session(&cookie_headers(&format!("theme=dark; {cookie}")), ip));
+	}
+	
+	#[test]
+	fn session_rejected_from_other_ip() {
+		let service = session_service();
+		let cookie = issued_cookie(&service, "192.0.2.1".parse().unwrap());
+		assert!(!service.verify_session(&cookie_headers(&cookie), "192.0.2.2".parse().unwrap()));
+	}
+	
+	#[test]
+	fn session_rejected_if_expired() {
+		let service = session_service();
+		let ip: IpAddr = "192.0.2.1".parse().unwrap();
+		let expires = unix_time() - 1;
+		let signature = service.sign_session(expires, ip);
+		let cookie = format!("{CAPTCHA_SESSION_COOKIE}={expires}.{signature}");
+		assert!(!service.verify_session(&cookie_headers(&cookie), ip));
+	}
+	
+	#[test]
+	fn session_rejected_if_tampered() {
+		let service = session_service();
+		let ip: IpAddr = "192.0.2.1".parse().unwrap();
+		let cookie = issued_cookie(&service, ip);
+		let (value, signature) = cookie.split_once('.').unwrap();
+		// Extending expiration invalidates signature
+		let (name, expires) = value.split_once('=').unwrap();
+		let extended = expires.parse::<u64>().unwrap() + 3600;
+		let cookie = format!("{name}={extended}.{signature}");
+		assert!(!service.verify_session(&cookie_headers(&cookie), ip));
+		let mut forged = signature.to_owned();
+		let last = if forged.ends_with('0') { "1" } else { "0" };
+		forged.replace_range(forged.len() - 1.., last);
+		let cookie = format!("{value}.{forged}");
+		assert!(!service.verify_session(&cookie_headers(&cookie), ip));
+	}
+	
+	#[test]
+	fn session_disabled_without_ttl() {
+		let config = AppConfig::from_toml(r#"data_dir = "/nonexistent""#).unwrap();
+		let service = RecaptchaService::new(config, reqwest::Client::new());
+		let ip: IpAddr = "192.0.2.1".parse().unwrap();
+		assert!(service.issue_session(ip).is_none());
+		let signature = service.sign_session(unix_time() + 60, ip);
+		let cookie = format!("{CAPTCHA_SESSION_COOKIE}={}.{signature}", unix_time() + 60);
+		assert!(!service.verify_session(&cookie_headers(&cookie), ip));
+	}
+	
+	#[test]
+	fn cache_evicts_expired_then_oldest() {
+		let now = Instant::now();
+		let ttl = Duration::from_secs(10);
+		let mut cache = VerdictCache::default();
+		cache.insert([1; 32], verdict(true, now + ttl), 2, now);
+		cache.insert([2; 32], verdict(false, now + ttl * 2), 2, now);
+		assert_eq!(cache.get(&[1; 32], now), Some(true));
+		assert_eq!(cache.get(&[2; 32], now), Some(false));
+		
+		cache.insert([3; 32], verdict(true, now + ttl * 3), 2, now);
+		assert_eq!(cache.get(&[1; 32], now), None);
+		assert_eq!(cache.get(&[3; 32], now), Some(true));
+		
+		let later = now + ttl * 2;
+		assert_eq!(cache.get(&[2; 32], later), None);
+		cache.insert([4; 32], verdict(true, later + ttl), 2, later);
+		assert_eq!(cache.verdicts.len(), 2);
+		assert_eq!(cache.get(&[3; 32], later), Some(true));
+	}
+	
+	#[test]
+	fn cache_skips_stale_order_entries() {
+		let now = Instant::now();
+		let ttl = Duration::from_secs(10);
+		let mut cache = VerdictCache::default();
+		cache.insert([1; 32], verdict(false, now + ttl), 2, now);
+		cache.insert([1; 32], verdict(true, now + ttl * 2), 2, now);
+		cache.insert([2; 32], verdict(true, now + ttl * 2), 2, now);
+		assert_eq!(cache.get(&[1; 32], now), Some(true));
+		assert_eq!(cache.get(&[2; 32], now), Some(true));
 	}
 }
@@ -34,7 +34,9 @@ impl TemplateService {
 	
 	pub fn index_ctx(&self) -> IndexPageCtx {
 		IndexPageCtx {
-			recaptcha_site_key: self.config.recaptcha_site_key.clone(),
+			captcha_provider: self.config.captcha_provider,
+			captcha_site_key: self.config.captcha_site_key.clone(),
+			captcha_session_ttl: Some(self.config.captcha_session_ttl).filter(|ttl| *ttl > 0),
 			osm_tiles_url: self.config.osm_tiles_url.clone(),
 		}
 	}
@@ -1,19 +1,46 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fs::{DirEntry, File};
 use std::path::{Path, PathBuf};
 use std::process::ExitStatus;
 use std::sync::{Arc, Weak};
 use std::time::Duration;
-use ahash::AHashMap;
+use ahash::{AHashMap, AHashSet};
 use arc_swap::ArcSwapOption;
+use chrono::{DateTime, Utc};
 use flate2::read::GzDecoder;
 use log::{error, info};
+use sha2::{Digest, Sha256};
 use tar::Archive;
 use thiserror::Error;
 use tokio::process::Command;
 use tokio::time::MissedTickBehavior;
 use crate::config::AppConfig;
-use crate::model::{ArchiveFileAuth, ArchiveFileInfo};
-use crate::state::{FileService, FileServiceError};
+use crate::model::{
+	ArchiveFileAuth, ArchiveFileInfo, TimezoneDatabaseStatus, TimezoneDetails, TimezoneListFormat,
+	TimezoneSource, TimezoneTransition, UpdateResult, UpdateStatus,
+};
+use crate::state::{FileService, FileServiceError, UpdateTracker};
+use crate::tzdata::{TzDataCompiler, TzDataError};
+use crate::tzif::TimeZone;
+
+pub const TZDATA_TAG: &str = "tzdata";
+const DEFAULT_TRANSITIONS: usize = 2;
+const MAX_TRANSITIONS: usize = 100;
+const MAX_LINK_DEPTH: usize = 16;
+
+const TZ_FILES: &[&str] = &[
+	"africa",
+	"antarctica",
+	"asia",
+	"australasia",
+	"etcetera",
+	"europe",
+	"northamerica",
+	"southamerica",
+	"backward",
+	"factory",
+];
 
 #[derive(Debug, Error)]
 pub enum TimezoneServiceError {
@@ -26,11 +53,141 @@ pub enum TimezoneServiceError {
 	#[error(transparent)]
 	Join(#[from] tokio::task::JoinError),
 	
+	#[error(transparent)]
+	TzData(#[from] TzDataError),
+	
 	#[error("Invalid file name")]
 	InvalidFileName,
 	
 	#[error("ZIC command failed with exit code {0}")]
-	Zic(ExitStatus)
+	Zic(ExitStatus),
+	
+	#[error("Archive is not extracted and data directory is read-only")]
+	NotExtracted,
+}
+
+#[derive(Default)]
+struct TimezoneDb {
+	zones: AHashMap<String, Arc<TimeZone>>,
+	/// Alias ids with canonical id they refer to.
+	links: AHashMap<String, String>,
+	/// Timezone database release, e.g. `2025b`.
+	version: Option<String>,
+	source: TimezoneSource,
+	/// Modification time of archive timezones were compiled from.
+	timestamp: Option<DateTime<Utc>>,
+}
+
+/// Timezone list in requested format, rendered only when needed
+/// so that conditional requests don't pay for building the body.
+pub struct TimezoneExport {
+	db: Option<Arc<TimezoneDb>>,
+	prefixes: Vec<String>,
+	format: TimezoneListFormat,
+	/// Already rendered body if it was needed for `etag`.
+	body: Option<String>,
+	/// Derived from timezone database release and query,
+	/// or from rendered content if the release is unknown.
+	pub etag: String,
+}
+
+impl TimezoneExport {
+	pub fn content_type(&self) -> &'static str {
+		match self.format {
+			TimezoneListFormat::Json => "application/json",
+			TimezoneListFormat::Csv | TimezoneListFormat::ZonesCsv => "text/csv; charset=utf-8",
+			TimezoneListFormat::CHeader => "text/x-c; charset=utf-8",
+		}
+	}
+	
+	pub fn render(mut self) -> String {
+		self.body.take().unwrap_or_else(|| self.render_body())
+	}
+	
+	fn render_body(&self) -> String {
+		let prefixes: Vec<&str> = self.prefixes.iter().map(String::as_str).collect();
+		let zones = self.db.as_ref()
+			.map(|db| db.list(&prefixes))
+			.unwrap_or_default();
+		let version = self.db.as_ref().and_then(|db| db.version.as_deref());
+		render_export(&zones, version, self.format)
+	}
+}
+
+impl TimezoneDb {
+	/// Timezones with ids starting with any of `prefixes` (all if empty)
+	/// mapped to POSIX TZ strings, sorted by id.
+	fn list(&self, prefixes: &[&str]) -> BTreeMap<String, String> {
+		self.zones.iter()
+			.filter(|(id, _)| {
+				prefixes.is_empty() || prefixes.iter().any(|prefix| id.starts_with(prefix))
+			})
+			.map(|(id, zone)| (id.clone(), zone.posix().to_owned()))
+			.collect()
+	}
+	
+	/// Resolves link chains and makes aliases available as zones,
+	/// dropping links to unknown zones.
+	fn resolve_links(&mut self) {
+		let links = std::mem::take(&mut self.links);
+		for (alias, target) in &links {
+			let mut canonical = target;
+			for _ in 0..MAX_LINK_DEPTH {
+				let Some(next) = links.get(canonical) else { break };
+				canonical = next;
+			}
+			if canonical == alias {
+				continue;
+			}
+			let Some(zone) = self.zones.get(canonical).cloned() else { continue };
+			self.zones.insert(alias.clone(), zone);
+			self.links.insert(alias.clone(), canonical.clone());
+		}
+	}
+	
+	/// Links timezones with identical data, preferring ids listed in
+	/// `zone1970.tab` (canonical ids only), then `zone.tab` as canonical ones.
+	fn link_identical(&mut self, root: &Path) {
+		let tabs: Vec<AHashSet<String>> = ["zone1970.tab", "zone.tab"].iter()
+			.map(|file| std::fs::read_to_string(root.join(file))
+				.unwrap_or_default()
+				.lines()
+				.filter(|line| !line.starts_with('#'))
+				.filter_map(|line| line.split('\t').nth(2).map(str::to_owned))
+				.collect())
+			.collect();
+		let mut ids: Vec<&String> = self.zones.keys()
+			.filter(|id| !self.links.contains_key(*id))
+			.collect();
+		ids.sort_by_key(|id| {
+			let rank = tabs.iter().position(|tab| tab.contains(*id)).unwrap_or(tabs.len());
+			(rank, *id)
+		});
+		let mut canonical: AHashMap<&str, Vec<&String>> = AHashMap::new();
+		let mut links = Vec::new();
+		for id in ids {
+			let zone = &self.zones[id];
+			let candidates = canonical.entry(zone.posix()).or_default();
+			match candidates.iter().find(|candidate| self.zones[**candidate] == *zone) {
+				Some(candidate) => links.push((id.clone(), (*candidate).clone())),
+				None => candidates.push(id),
+			}
+		}
+		self.links.extend(links);
+	}
+	
+	fn canonical<'a>(&'a self, id: &'a str) -> &'a str {
+		self.links.get(id).map_or(id, String::as_str)
+	}
+	
+	fn aliases(&self, canonical: &str) -> Vec<String> {
+		let mut aliases: Vec<String> = self.links.iter()
+			.filter(|(_, target)| *target == canonical)
+			.map(|(alias, _)| alias.clone())
+			.collect();
+		aliases.sort();
+		aliases
+	}
 }
 
 pub struct TimezoneService {
@@ -38,7 +195,9 @@ pub struct TimezoneService {
 	config: Arc<AppConfig>,
 	files: Arc<FileService>,
 	zic_path: Option<PathBuf>,
-	timezones: ArcSwapOption<AHashMap<String, String>>,
+	timezones: ArcSwapOption<TimezoneDb>,
+	update: UpdateTracker,
+	error: ArcSwapOption<String>,
 }
 
 impl TimezoneService {
@@ -48,13 +207,13 @@ impl TimezoneService {
 	) -> Arc<Self> {
 		let zic_path = Self::find_zic(&config);
 		let mut timezones = Self::load_from_system();
-		if let Some(zic_path) = &zic_path {
-			if let Some(info) = files.get_latest_archive("tzdata") {
-				match Self::load_from_archive(&info, zic_path).await {
-					Ok(tz) => timezones = tz,
-					Err(err) => {
-						error!("Unable to load timezone database from archive: {err}");
-					}
+		let mut load_error = None;
+		if let Some(info) = files.get_latest_archive(TZDATA_TAG) {
+			match Self::load_from_archive(&info, zic_path.as_deref(), files.is_read_only()).await {
+				Ok(tz) => timezones = tz,
+				Err(err) => {
+					error!("Unable to load timezone database from archive: {err}");
+					load_error = Some(Arc::new(err.to_string()));
 				}
 			}
 		}
@@ -65,6 +224,8 @@ impl TimezoneService {
 			files,
 			zic_path,
 			timezones: ArcSwapOption::from_pointee(timezones),
+			update: UpdateTracker::new(TZDATA_TAG),
+			error: ArcSwapOption::new(load_error),
 		})
 	}
 	
@@ -79,79 +240,152 @@ impl TimezoneService {
 				Some(zic_path)
 			}
 			Err(err) => {
-				error!("Unable to find zic path: {err}");
+				info!("Unable to find zic path, using built-in compiler: {err}");
 				None
 			}
 		}
 	}
 	
+	/// Loads timezones from archive, compiled by zic if available. In `read_only`
+	/// mode only sources (and zic output) extracted previously are used,
+	/// nothing is written.
 	async fn load_from_archive(
 		info: &ArchiveFileInfo,
-		zic_path: &Path,
-	) -> Result<AHashMap<String, String>, TimezoneServiceError> {
-		let path = info.path.clone();
-		let path = tokio::task::spawn_blocking(move || Self::decompress(&path)).await??;
-		let path = Self::compile(&path, zic_path).await?;
-		let mut timezones = AHashMap::new();
-		Self::load_from_dir(&path, "", &mut timezones)?;
-		info!("Loaded {} timezones from {}", timezones.len(), info.path.display());
-		Ok(timezones)
-	}
-	
-	fn load_from_system() -> AHashMap<String, String> {
-		let mut out = AHashMap::new();
+		zic_path: Option<&Path>,
+		read_only: bool,
+	) -> Result<TimezoneDb, TimezoneServiceError> {
+		let path = if read_only {
+			let path = Self::extracted_path(&info.path);
+			if !path.is_dir() {
+				return Err(TimezoneServiceError::NotExtracted);
+			}
+			path
+		} else {
+			let path = info.path.clone();
+			tokio::task::spawn_blocking(move || Self::decompress(&path)).await??
+		};
+		let source_path = path.clone();
+		let data = tokio::task::spawn_blocking(move || {
+			TzDataCompiler::compile_dir(&source_path, TZ_FILES)
+		}).await?;
+		let compiled = if read_only {
+			Some(path.with_added_extension("zoneinfo")).filter(|path| path.is_dir())
+		} else if let Some(zic_path) = zic_path {
+			Some(Self::compile(&path, zic_path).await?)
+		} else {
+			None
+		};
+		let mut db = if let Some(path) = compiled {
+			let mut db = TimezoneDb::default();
+			Self::load_from_dir(&path, "", &mut db)?;
+			match data {
+				Ok(data) => db.links = data.links,
+				Err(err) => error!("Unable to read timezone links: {err}"),
+			}
+			db
+		} else {
+			let data = data?;
+			TimezoneDb {
+				zones: data.zones.into_iter()
+					.filter_map(|(name, posix)| Some((name, Arc::new(TimeZone::from_posix(posix)?))))
+					.collect(),
+				links: data.links,
+				..Default::default()
+			}
+		};
+		db.version = Self::read_version(&path);
+		db.source = TimezoneSource::Archive;
+		db.timestamp = Some(info.mtime);
+		db.resolve_links();
+		info!("Loaded {} timezones from {}", db.zones.len(), info.path.display());
+		Ok(db)
+	}
+	
+	fn load_from_system() -> TimezoneDb {
+		let mut out = TimezoneDb::default();
 		let path = Path::new("/usr/share/zoneinfo");
 		Self::maybe_load_from_dir(path, "", &mut out);
-		info!("Loaded {} system timezones", out.len());
+		match TzDataCompiler::compile_dir(path, &["tzdata.zi"]) {
+			Ok(data) => out.links.extend(data.links),
+			Err(err) => {
+				info!("Unable to read system timezone links, detecting identical timezones: {err}");
+				out.link_identical(path);
+			}
+		}
+		out.version = Self::read_version(path);
+		out.resolve_links();
+		info!("Loaded {} system timezones", out.zones.len());
 		out
 	}
 	
+	/// Reads release from `version` file of sources or header of `tzdata.zi`.
+	fn read_version(root: &Path) -> Option<String> {
+		if let Ok(version) = std::fs::read_to_string(root.join("version")) {
+			return Some(version.trim().to_owned()).filter(|version| !version.is_empty());
+		}
+		let zi = std::fs::read_to_string(root.join("tzdata.zi")).ok()?;
+		let version = zi.lines().next()?.strip_prefix("# version ")?.trim();
+		Some(version.to_owned()).filter(|version| !version.is_empty())
+	}
+	
 	fn maybe_load_from_dir(
-		path: &Path,
+		root: &Path,
 		prefix: &str,
-		out: &mut AHashMap<String, String>,
+		out: &mut TimezoneDb,
 	) {
-		if let Err(err) = Self::load_from_dir(path, prefix, out) {
-			error!("Unable to load timezones from {}: {err}", path.display());
+		if let Err(err) = Self::load_from_dir(root, prefix, out) {
+			error!("Unable to load timezones from {}: {err}", root.join(prefix).display());
 		}
 	}
 	
 	fn load_from_dir(
-		path: &Path,
+		root: &Path,
 		prefix: &str,
-		out: &mut AHashMap<String, String>,
+		out: &mut TimezoneDb,
 	) -> Result<(), TimezoneServiceError> {
-		for entry in std::fs::read_dir(path)? {
+		for entry in std::fs::read_dir(root.join(prefix))? {
 			let entry = entry?;
-			Self::maybe_load_from_dir_entry(&entry, prefix, out);
+			Self::maybe_load_from_dir_entry(root, &entry, prefix, out);
 		}
 		Ok(())
 	}
 	
 	fn maybe_load_from_dir_entry(
+		root: &Path,
 		entry: &DirEntry,
 		prefix: &str,
-		out: &mut AHashMap<String, String>,
+		out: &mut TimezoneDb,
 	) {
-		if let Err(err) = Self::load_from_dir_entry(entry, prefix, out) {
+		if let Err(err) = Self::load_from_dir_entry(root, entry, prefix, out) {
 			error!("Unable to load timezones from {}: {err}", entry.path().display());
 		}
 	}
 	
 	fn load_from_dir_entry(
+		root: &Path,
 		entry: &DirEntry,
 		prefix: &str,
-		out: &mut AHashMap<String, String>,
+		out: &mut TimezoneDb,
 	) -> Result<(), TimezoneServiceError> {
 		let file_name = entry.file_name();
 		let file_name = file_name.to_str()
 			.ok_or_else(|| TimezoneServiceError::InvalidFileName)?;
 		let file_type = entry.file_type()?;
 		if file_type.is_dir() {
-			Self::load_from_dir(&entry.path(), &format!("{prefix}{file_name}/"), out)?;
+			Self::load_from_dir(root, &format!("{prefix}{file_name}/"), out)?;
 		} else if file_type.is_file() {
 			let bytes = std::fs::read(entry.path())?;
 			Self::load_from_slice(&format!("{prefix}{file_name}"), &bytes, out)?;
+		} else if file_type.is_symlink() {
+			// Symlinked files are aliases, symlinked directories are skipped
+			let target = std::fs::canonicalize(entry.path())?;
+			let root = std::fs::canonicalize(root)?;
+			if target.is_file()
+				&& let Ok(target) = target.strip_prefix(&root)
+				&& let Some(target) = target.to_str()
+			{
+				out.links.insert(format!("{prefix}{file_name}"), target.to_owned());
+			}
 		}
 		Ok(())
 	}
@@ -159,69 +393,88 @@ impl TimezoneService {
 	fn load_from_slice(
 		name: &str,
 		bytes: &[u8],
-		out: &mut AHashMap<String, String>,
+		out: &mut TimezoneDb,
 	) -> Result<(), TimezoneServiceError> {
-		if !bytes.starts_with(b"TZif") {
-			return Ok(());
-		}
-		let Some(bytes) = bytes.strip_suffix(b"\n") else { return Ok(()) };
-		let Some(i) = bytes.iter().rposition(|c| *c == b'\n') else { return Ok(()) };
-		let spec = &bytes[i + 1..];
-		let Ok(spec) = str::from_utf8(spec) else { return Ok(()) };
-		let spec = spec.trim();
-		if spec.is_empty() {
-			return Ok(());
-		}
-		out.insert(name.to_owned(), spec.to_owned());
+		let Some(timezone) = TimeZone::from_tzif(bytes) else { return Ok(()) };
+		out.zones.insert(name.to_owned(), Arc::new(timezone));
 		Ok(())
 	}
 	
 	pub fn start_updater(&self) {
 		let me = self.me.upgrade().expect("Unable to upgrade me");
-		if self.zic_path.is_none() {
-			info!("Cannot perform timezone database auto-update without zic executable");
-			return;
-		}
 		let Some(interval) = self.config.tzdata_auto_update_interval else {
 			info!("Timezone database auto-update is disabled");
 			return;
 		};
 		info!("Timezone database auto-update interval: {interval} hour(s)");
 		tokio::task::spawn(async move {
-			let mut interval = tokio::time::interval(Duration::from_hours(interval));
+			let period = Duration::from_hours(interval);
+			let mut interval = tokio::time::interval(period);
 			interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
 			loop {
 				interval.tick().await;
-				if let Err(err) = me.update().await {
-					error!("Unable to update timezone database: {err}");
-				}
+				let started = Utc::now();
+				me.run_update(false).await;
+				me.update.schedule(started + period);
 			}
 		});
 	}
 	
-	async fn update(&self) -> Result<(), TimezoneServiceError> {
-		let Some(zic_path) = self.zic_path.as_ref() else { return Ok(()) };
+	/// Updates timezone database bypassing freshness check.
+	pub async fn force_update(&self) -> UpdateResult {
+		self.run_update(true).await
+	}
+	
+	pub fn update_status(&self) -> UpdateStatus {
+		self.update.status()
+	}
+	
+	async fn run_update(&self, force: bool) -> UpdateResult {
+		let res = self.update.run(self.update(force)).await;
+		if let Err(err) = &res {
+			error!("Unable to update timezone database: {err}");
+		}
+		self.error.store(res.as_ref().err().map(|err| Arc::new(err.to_string())));
+		UpdateResult {
+			target: TZDATA_TAG.to_owned(),
+			updated: matches!(res, Ok(true)),
+			error: res.err().map(|err| err.to_string()),
+		}
+	}
+	
+	async fn update(&self, force: bool) -> Result<bool, TimezoneServiceError> {
 		info!("Updating timezone database...");
+		let interval = if force {
+			Duration::ZERO
+		} else {
+			Duration::from_hours(self.config.tzdata_auto_update_interval.unwrap_or(0))
+		};
 		let info = self.files.refresh_archive(
-			"tzdata",
+			TZDATA_TAG,
 			&self.config.tzdata_download_url,
+			None,
 			if let Some(token) = &self.config.tzdata_bearer_token {
 				ArchiveFileAuth::Bearer(token.clone())
 			} else {
 				ArchiveFileAuth::None
 			},
-			Duration::from_hours(self.config.tzdata_auto_update_interval.unwrap_or(0)),
+			interval,
 		).await?;
-		let Some(info) = info else { return Ok(()) };
-		let timezones = Self::load_from_archive(&info, zic_path).await?;
+		let Some(info) = info else { return Ok(false) };
+		let timezones = Self::load_from_archive(&info, self.zic_path.as_deref(), false).await?;
 		self.timezones.store(Some(Arc::new(timezones)));
 		info!("Using new timezone database");
-		Ok(())
+		Ok(true)
 	}
 	
-	fn decompress(path: &Path) -> Result<PathBuf, TimezoneServiceError> {
+	fn extracted_path(path: &Path) -> PathBuf {
 		let mut out_dir = path.with_extension("");
 		out_dir.set_extension("");
+		out_dir
+	}
+	
+	fn decompress(path: &Path) -> Result<PathBuf, TimezoneServiceError> {
+		let out_dir = Self::extracted_path(path);
 		if out_dir.exists() {
 			return Ok(out_dir);
 		}
@@ -240,18 +493,6 @@ impl TimezoneService {
 		cmd.current_dir(path);
 		cmd.arg("-d").arg(abs_out_dir);
 		cmd.arg("-L").arg("leapseconds");
-		const TZ_FILES: &[&str] = &[
-			"africa",
-			"antarctica",
-			"asia",
-			"australasia",
-			"etcetera",
-			"europe",
-			"northamerica",
-			"southamerica",
-			"backward",
-			"factory",
-		];
 		for tz_file in TZ_FILES {
 			cmd.arg(tz_file);
 		}
@@ -268,20 +509,154 @@ impl TimezoneService {
 		Ok(out_dir)
 	}
 	
-	pub fn get_all(&self) -> AHashMap<String, String> {
+	pub fn status(&self) -> TimezoneDatabaseStatus {
+		let db = self.timezones.load();
+		let db = db.as_deref();
+		TimezoneDatabaseStatus {
+			source: db.map(|db| db.source).unwrap_or_default(),
+			version: db.and_then(|db| db.version.clone()),
+			zones: db.map_or(0, |db| {
+				db.zones.keys().filter(|id| !db.links.contains_key(*id)).count()
+			}),
+			links: db.map_or(0, |db| db.links.len()),
+			timestamp: db.and_then(|db| db.timestamp),
+			last_update_check: self.get_archive().map(|info| info.utime),
+			error: self.error.load().as_ref().map(|err| (**err).clone()),
+		}
+	}
+	
+	pub fn export(&self, prefixes: &[&str], format: TimezoneListFormat) -> TimezoneExport {
+		let db = self.timezones.load_full();
+		let mut prefixes: Vec<String> = prefixes.iter().map(|prefix| (*prefix).to_owned()).collect();
+		prefixes.sort_unstable();
+		prefixes.dedup();
+		let mut export = TimezoneExport { db, prefixes, format, body: None, etag: String::new() };
+		let version = export.db.as_ref().and_then(|db| db.version.as_deref());
+		let digest = match version {
+			Some(version) => Sha256::new()
+				.chain_update(version)
+				.chain_update(format!("\0{format:?}\0"))
+				.chain_update(export.prefixes.join(","))
+				.finalize(),
+			None => {
+				let body = export.render_body();
+				let digest = Sha256::digest(body.as_bytes());
+				export.body = Some(body);
+				digest
+			}
+		};
+		let version = version
+			.unwrap_or("unknown")
+			.chars()
+			.filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+			.collect::<String>();
+		let digest = format!("{digest:x}");
+		export.etag = format!("\"{version}-{}\"", &digest[..16]);
+		export
+	}
+	
+	pub fn lookup(&self, id: &str) -> Option<Arc<TimeZone>> {
 		self.timezones.load()
 			.as_ref()
-			.map(|zones| (**zones).clone())
-			.unwrap_or_default()
+			.and_then(|db| db.zones.get(id).cloned())
 	}
 	
-	pub fn lookup(&self, id: &str) -> Option<String> {
+	/// Canonical id of timezone, `id` itself if it isn't an alias.
+	pub fn canonical(&self, id: &str) -> String {
 		self.timezones.load()
 			.as_ref()
-			.and_then(|zones| zones.get(id).cloned())
+			.map_or(id, |db| db.canonical(id))
+			.to_owned()
+	}
+	
+	/// Current state and upcoming transitions of timezone,
+	/// `transitions` defaults to 2 and is capped at 100.
+	pub fn details(&self, id: &str, transitions: Option<usize>) -> Option<TimezoneDetails> {
+		let db = self.timezones.load_full()?;
+		let zone = db.zones.get(id)?;
+		let canonical = db.canonical(id);
+		let now = Utc::now().timestamp();
+		let local = zone.local_time_type(now);
+		let count = transitions.unwrap_or(DEFAULT_TRANSITIONS).min(MAX_TRANSITIONS);
+		Some(TimezoneDetails {
+			id: id.to_owned(),
+			canonical: canonical.to_owned(),
+			aliases: db.aliases(canonical),
+			posix_timezone: zone.posix().to_owned(),
+			utc_offset: local.utc_offset,
+			abbreviation: local.abbreviation.clone(),
+			dst: local.dst,
+			transitions: zone.next_transitions(now, count)
+				.into_iter()
+				.filter_map(|(time, local)| Some(TimezoneTransition {
+					time: DateTime::from_timestamp(time, 0)?,
+					utc_offset: local.utc_offset,
+					abbreviation: local.abbreviation.clone(),
+					dst: local.dst,
+				}))
+				.collect(),
+		})
 	}
 	
 	pub fn get_archive(&self) -> Option<Arc<ArchiveFileInfo>> {
-		self.files.get_latest_archive("tzdata")
+		self.files.get_latest_archive(TZDATA_TAG)
 	}
 }
+
+/// Quotes CSV field if it contains separator, quote or line break.
+fn render_export(
+	zones: &BTreeMap<String, String>,
+	version: Option<&str>,
+	format: TimezoneListFormat,
+) -> String {
+	match format {
+		TimezoneListFormat::Json => serde_json::to_string(zones).unwrap_or_default(),
+		TimezoneListFormat::Csv => {
+			let mut body = String::from("id,posix_timezone\n");
+			for (id, posix) in zones {
+				body.push_str(&format!("{},{}\n", csv_field(id), csv_field(posix)));
+			}
+			body
+		}
+		TimezoneListFormat::ZonesCsv => {
+			let mut body = String::new();
+			for (id, posix) in zones {
+				body.push_str(&format!("{},{}\n", csv_quoted(id), csv_quoted(posix)));
+			}
+			body
+		}
+		TimezoneListFormat::CHeader => {
+			let mut body = format!(
+				"/* POSIX timezones, tzdata {} */\n\
+				#pragma once\n\n\
+				typedef struct {{\n\tconst char *id;\n\tconst char *posix;\n}} posix_timezone_t;\n\n\
+				static const posix_timezone_t POSIX_TIMEZONES[] = {{\n",
+				version.unwrap_or("unknown"),
+			);
+			for (id, posix) in zones {
+				body.push_str(&format!("\t{{{}, {}}},\n", c_string(id), c_string(posix)));
+			}
+			body.push_str(
+				"};\n\n#define POSIX_TIMEZONES_COUNT \
+				(sizeof(POSIX_TIMEZONES) / sizeof(POSIX_TIMEZONES[0]))\n",
+			);
+			body
+		}
+	}
+}
+
+fn csv_field(value: &str) -> Cow<'_, str> {
+	if value.contains([',', '"', '\r', '\n']) {
+		Cow::Owned(csv_quoted(value))
+	} else {
+		Cow::Borrowed(value)
+	}
+}
+
+fn csv_quoted(value: &str) -> String {
+	format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+fn c_string(value: &str) -> String {
+	format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,82 @@
+use std::fmt::Display;
+use std::sync::Arc;
+use arc_swap::ArcSwap;
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use crate::model::UpdateStatus;
+
+/// Serializes updates of a single target (edition or tzdata)
+/// and keeps track of their outcomes.
+pub struct UpdateTracker {
+	lock: Mutex<()>,
+	status: ArcSwap<UpdateStatus>,
+}
+
+impl UpdateTracker {
+	pub fn new(target: &str) -> Self {
+		Self {
+			lock: Mutex::new(()),
+			status: ArcSwap::from_pointee(UpdateStatus {
+				target: target.to_owned(),
+				..Default::default()
+			}),
+		}
+	}
+	
+	/// Runs `update` unless another update of the same target is running,
+	/// in which case waits for it to finish first.
+	pub async fn run<T, E: Display>(
+		&self,
+		update: impl Future<Output = Result<T, E>>,
+	) -> Result<T, E> {
+		let _guard = self.lock.lock().await;
+		self.status.rcu(|status| Arc::new(UpdateStatus {
+			running: true,
+			..(**status).clone()
+		}));
+		let res = update.await;
+		let now = Utc::now();
+		self.status.rcu(|status| {
+			let mut status = (**status).clone();
+			status.running = false;
+			match &res {
+				Ok(_) => {
+					status.last_success = Some(now);
+				},
+				Err(err) => {
+					status.last_failure = Some(now);
+					status.last_error = Some(err.to_string());
+				},
+			}
+			Arc::new(status)
+		});
+		res
+	}
+	
+	pub fn schedule(&self, next_update: DateTime<Utc>) {
+		self.status.rcu(|status| Arc::new(UpdateStatus {
+			next_update: Some(next_update),
+			..(**status).clone()
+		}));
+	}
+	
+	pub fn schedule_retry(&self, attempts: u32, next_retry: DateTime<Utc>) {
+		self.status.rcu(|status| Arc::new(UpdateStatus {
+			retry_attempts: attempts,
+			next_retry: Some(next_retry),
+			..(**status).clone()
+		}));
+	}
+	
+	pub fn clear_retry(&self) {
+		self.status.rcu(|status| Arc::new(UpdateStatus {
+			retry_attempts: 0,
+			next_retry: None,
+			..(**status).clone()
+		}));
+	}
+	
+	pub fn status(&self) -> UpdateStatus {
+		(**self.status.load()).clone()
+	}
+}
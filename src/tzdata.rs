@@ -0,0 +1,736 @@
+use std::cmp::Ordering;
+use std::path::Path;
+use ahash::AHashMap;
+use thiserror::Error;
+
+const SECS_PER_HOUR: i64 = 60 * 60;
+const SECS_PER_DAY: i64 = 24 * SECS_PER_HOUR;
+const DAYS_PER_WEEK: i64 = 7;
+const MAX_YEAR: i64 = i64::MAX;
+const MIN_YEAR: i64 = i64::MIN;
+const MAX_LINK_DEPTH: usize = 16;
+
+const LINE_KEYWORDS: &[&str] = &["Rule", "Zone", "Link"];
+const MONTH_NAMES: &[&str] = &[
+	"January", "February", "March", "April", "May", "June",
+	"July", "August", "September", "October", "November", "December",
+];
+const WEEKDAY_NAMES: &[&str] = &[
+	"Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday",
+];
+const DAYS_IN_MONTH: [i64; 12] = [31, 28, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+const DAYS_IN_MONTH_LEAP: [i64; 12] = [31, 29, 31, 30, 31, 30, 31, 31, 30, 31, 30, 31];
+
+#[derive(Debug, Error)]
+pub enum TzDataError {
+	#[error(transparent)]
+	Io(#[from] std::io::Error),
+	
+	#[error("{file}:{line}: {reason}")]
+	Syntax {
+		file: String,
+		line: usize,
+		reason: &'static str,
+	},
+}
+
+#[derive(Debug, Clone, Copy)]
+enum RuleDay {
+	/// Fixed day of month.
+	Fixed(i64),
+	/// First given weekday on or after day of month.
+	AtLeast(i64, i64),
+	/// Last given weekday on or before day of month.
+	AtMost(i64, i64),
+}
+
+impl RuleDay {
+	fn day_of_month(self) -> i64 {
+		match self {
+			RuleDay::Fixed(day) | RuleDay::AtLeast(_, day) | RuleDay::AtMost(_, day) => day,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+	to_year: i64,
+	month: usize,
+	day: RuleDay,
+	time: i64,
+	/// Transition time is in standard time rather than wall clock time.
+	time_std: bool,
+	/// Transition time is in UT rather than wall clock time.
+	time_ut: bool,
+	save: i64,
+	is_dst: bool,
+	/// Value substituted for `%s` in zone format, absent only for synthesized rules.
+	letters: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+enum ZoneRules {
+	Fixed(i64, bool),
+	Named(String),
+}
+
+#[derive(Debug, Clone)]
+struct ZoneLine {
+	std_offset: i64,
+	rules: ZoneRules,
+	format: String,
+}
+
+/// Compiled timezone database.
+#[derive(Debug, Default)]
+pub struct TzData {
+	/// POSIX TZ strings by zone or link name.
+	pub zones: AHashMap<String, String>,
+	/// Link names with zone they finally refer to.
+	pub links: AHashMap<String, String>,
+}
+
+/// Minimal IANA tzdata source compiler, producing the POSIX TZ string
+/// `zic` would write into TZif footer of every zone and link.
+/// Only the current (last) line of each zone is kept, as the footer
+/// describes the ongoing rules only.
+#[derive(Debug, Default)]
+pub struct TzDataCompiler {
+	rules: AHashMap<String, Vec<Rule>>,
+	zones: AHashMap<String, ZoneLine>,
+	links: Vec<(String, String)>,
+}
+
+impl TzDataCompiler {
+	/// Compiles given source files of directory into POSIX TZ strings by zone name.
+	pub fn compile_dir(dir: &Path, files: &[&str]) -> Result<TzData, TzDataError> {
+		let mut compiler = Self::default();
+		for file in files {
+			compiler.parse_file(&dir.join(file))?;
+		}
+		Ok(compiler.finish())
+	}
+	
+	pub fn parse_file(&mut self, path: &Path) -> Result<(), TzDataError> {
+		let source = std::fs::read_to_string(path)?;
+		let file = path.file_name()
+			.map(|name| name.to_string_lossy().into_owned())
+			.unwrap_or_default();
+		self.parse(&file, &source)
+	}
+	
+	pub fn parse(&mut self, file: &str, source: &str) -> Result<(), TzDataError> {
+		let syntax = |line: usize, reason: &'static str| TzDataError::Syntax {
+			file: file.to_owned(),
+			line,
+			reason,
+		};
+		let mut continued_zone: Option<String> = None;
+		let mut line_no = 0;
+		for (i, line) in source.lines().enumerate() {
+			line_no = i + 1;
+			let line = line.split('#').next().unwrap_or_default();
+			let fields: Vec<&str> = line.split_whitespace().collect();
+			if fields.is_empty() {
+				continue;
+			}
+			let res = if let Some(name) = continued_zone.take() {
+				self.parse_zone_line(name, &fields)
+			} else {
+				match lookup_word(fields[0], LINE_KEYWORDS) {
+					Some(0) => self.parse_rule(&fields[1..]).map(|_| None),
+					Some(1) => match fields.get(1) {
+						Some(name) => self.parse_zone_line((*name).to_owned(), &fields[2..]),
+						None => Err("Missing zone name"),
+					},
+					Some(2) => self.parse_link(&fields[1..]).map(|_| None),
+					_ => Err("Unknown line keyword"),
+				}
+			};
+			continued_zone = res.map_err(|reason| syntax(line_no, reason))?;
+		}
+		if continued_zone.is_some() {
+			return Err(syntax(line_no, "Missing zone continuation line"));
+		}
+		Ok(())
+	}
+	
+	fn parse_rule(&mut self, fields: &[&str]) -> Result<(), &'static str> {
+		let [name, from, to, _type, month, day, time, save, letters] = fields else {
+			return Err("Wrong number of fields on rule line");
+		};
+		let from_year = match lookup_word(from, &["minimum", "maximum"]) {
+			Some(0) => MIN_YEAR,
+			Some(_) => MAX_YEAR,
+			None => from.parse().map_err(|_| "Invalid starting year")?,
+		};
+		let to_year = match lookup_word(to, &["minimum", "maximum", "only"]) {
+			Some(0) => MIN_YEAR,
+			Some(1) => MAX_YEAR,
+			Some(_) => from_year,
+			None => to.parse().map_err(|_| "Invalid ending year")?,
+		};
+		let month = lookup_word(month, MONTH_NAMES).ok_or("Invalid month name")?;
+		let day = parse_day(day, month)?;
+		let (time, suffix) = parse_time(time)?;
+		let (time_std, time_ut) = match suffix {
+			None | Some('w') => (false, false),
+			Some('s') => (true, false),
+			Some('u' | 'g' | 'z') => (true, true),
+			_ => return Err("Invalid time of day"),
+		};
+		let (save, is_dst) = parse_save(save)?;
+		let letters = if *letters == "-" { "" } else { letters };
+		self.rules.entry((*name).to_owned()).or_default().push(Rule {
+			to_year,
+			month,
+			day,
+			time,
+			time_std,
+			time_ut,
+			save,
+			is_dst,
+			letters: Some(letters.to_owned()),
+		});
+		Ok(())
+	}
+	
+	/// Parses zone or zone continuation line, returning zone name if
+	/// another continuation line is expected.
+	fn parse_zone_line(&mut self, name: String, fields: &[&str]) -> Result<Option<String>, &'static str> {
+		let [std_offset, rules, format, until @ ..] = fields else {
+			return Err("Wrong number of fields on zone line");
+		};
+		let (std_offset, _) = parse_time(std_offset)?;
+		let rules = if *rules == "-" {
+			ZoneRules::Fixed(0, false)
+		} else if rules.starts_with(|c: char| c.is_ascii_digit() || c == '-') {
+			let (save, is_dst) = parse_save(rules)?;
+			ZoneRules::Fixed(save, is_dst)
+		} else {
+			ZoneRules::Named((*rules).to_owned())
+		};
+		self.zones.insert(name.clone(), ZoneLine {
+			std_offset,
+			rules,
+			format: (*format).to_owned(),
+		});
+		Ok((!until.is_empty()).then_some(name))
+	}
+	
+	fn parse_link(&mut self, fields: &[&str]) -> Result<(), &'static str> {
+		let [target, name] = fields else {
+			return Err("Wrong number of fields on link line");
+		};
+		self.links.push(((*target).to_owned(), (*name).to_owned()));
+		Ok(())
+	}
+	
+	/// Builds POSIX TZ strings of all parsed zones and links.
+	/// Zones whose current rules can't be expressed as POSIX TZ string are omitted.
+	pub fn finish(self) -> TzData {
+		let mut out = TzData {
+			zones: self.zones.iter()
+				.filter_map(|(name, zone)| Some((name.clone(), self.footer(zone)?)))
+				.collect(),
+			links: AHashMap::new(),
+		};
+		let links: AHashMap<&str, &str> = self.links.iter()
+			.map(|(target, name)| (name.as_str(), target.as_str()))
+			.collect();
+		for &name in links.keys() {
+			let mut target = name;
+			for _ in 0..MAX_LINK_DEPTH {
+				let Some(next) = links.get(target) else { break };
+				target = next;
+			}
+			if !self.zones.contains_key(target) {
+				continue;
+			}
+			if let Some(spec) = out.zones.get(target).cloned() {
+				out.zones.insert(name.to_owned(), spec);
+			}
+			out.links.insert(name.to_owned(), target.to_owned());
+		}
+		out
+	}
+	
+	/// Mirrors `stringzone` of `zic`.
+	fn footer(&self, zone: &ZoneLine) -> Option<String> {
+		let (rules, zone_save, zone_is_dst) = match &zone.rules {
+			ZoneRules::Fixed(save, is_dst) => (&[][..], *save, *is_dst),
+			ZoneRules::Named(name) => (self.rules.get(name)?.as_slice(), 0, false),
+		};
+		let mut std_rule: Option<&Rule> = None;
+		let mut dst_rule: Option<&Rule> = None;
+		for rule in rules {
+			let last = if rule.is_dst { &mut dst_rule } else { &mut std_rule };
+			match cmp_rules(*last, Some(rule)) {
+				Ordering::Less => *last = Some(rule),
+				Ordering::Equal => return None,
+				Ordering::Greater => {}
+			}
+		}
+		let dst_cmp = if !rules.is_empty() {
+			cmp_rules(dst_rule, std_rule)
+		} else if zone_is_dst {
+			Ordering::Greater
+		} else {
+			Ordering::Less
+		};
+		let mut std_zone = (zone.std_offset, zone.format.as_str());
+		let mut dst_zone = std_zone;
+		let synthesized;
+		let (dst_rule, std_rule) = match dst_cmp {
+			Ordering::Less => (None, std_rule),
+			Ordering::Equal => (dst_rule, std_rule),
+			Ordering::Greater => {
+				// DST all year, expressed as a transition at year start and end
+				let save = dst_rule.map_or(zone_save, |rule| rule.save);
+				if save >= 0 {
+					std_zone = (zone.std_offset + 2 * save, "XXX");
+					dst_zone = (std_zone.0, zone.format.as_str());
+				}
+				let dst_save = if save < 0 { save } else { -save };
+				synthesized = [
+					Rule {
+						to_year: MAX_YEAR,
+						month: 0,
+						day: RuleDay::Fixed(1),
+						time: 0,
+						time_std: false,
+						time_ut: false,
+						save: dst_save,
+						is_dst: true,
+						letters: dst_rule.and_then(|rule| rule.letters.clone()),
+					},
+					Rule {
+						to_year: MAX_YEAR,
+						month: 11,
+						day: RuleDay::Fixed(31),
+						time: SECS_PER_DAY + dst_save,
+						time_std: false,
+						time_ut: false,
+						save: 0,
+						is_dst: false,
+						letters: std_rule.filter(|_| save < 0).and_then(|rule| rule.letters.clone()),
+					},
+				];
+				(Some(&synthesized[0]), Some(&synthesized[1]))
+			}
+		};
+		let std_letters = std_rule.and_then(|rule| rule.letters.as_deref());
+		let mut out = format_abbr(std_zone.1, std_zone.0, std_letters, false, 0);
+		push_offset(&mut out, -std_zone.0)?;
+		let Some(dst_rule) = dst_rule else { return Some(out) };
+		let std_rule = std_rule?;
+		out.push_str(&format_abbr(
+			dst_zone.1,
+			dst_zone.0,
+			dst_rule.letters.as_deref(),
+			dst_rule.is_dst,
+			dst_rule.save,
+		));
+		if dst_rule.save != SECS_PER_HOUR {
+			push_offset(&mut out, -(dst_zone.0 + dst_rule.save))?;
+		}
+		out.push(',');
+		push_rule(&mut out, dst_rule, dst_rule.save, std_zone.0)?;
+		out.push(',');
+		push_rule(&mut out, std_rule, dst_rule.save, std_zone.0)?;
+		Some(out)
+	}
+}
+
+/// Orders rules by the time they last apply, absent rule being the earliest.
+fn cmp_rules(a: Option<&Rule>, b: Option<&Rule>) -> Ordering {
+	let (a, b) = match (a, b) {
+		(None, None) => return Ordering::Equal,
+		(None, Some(_)) => return Ordering::Less,
+		(Some(_), None) => return Ordering::Greater,
+		(Some(a), Some(b)) => (a, b),
+	};
+	if a.to_year != b.to_year {
+		return a.to_year.cmp(&b.to_year);
+	}
+	if a.to_year == MAX_YEAR {
+		return Ordering::Equal;
+	}
+	a.month.cmp(&b.month)
+		.then(a.day.day_of_month().cmp(&b.day.day_of_month()))
+}
+
+/// Finds word in table, ignoring case and allowing unambiguous abbreviations.
+fn lookup_word(word: &str, table: &[&str]) -> Option<usize> {
+	if let Some(i) = table.iter().position(|item| item.eq_ignore_ascii_case(word)) {
+		return Some(i);
+	}
+	let mut found = None;
+	for (i, item) in table.iter().enumerate() {
+		let is_prefix = item.get(..word.len())
+			.is_some_and(|prefix| prefix.eq_ignore_ascii_case(word));
+		if is_prefix {
+			if found.is_some() {
+				return None;
+			}
+			found = Some(i);
+		}
+	}
+	found
+}
+
+fn parse_day(day: &str, month: usize) -> Result<RuleDay, &'static str> {
+	const INVALID: &str = "Invalid day of month";
+	if let Some(weekday) = day.strip_prefix("last") {
+		let weekday = weekday.strip_prefix('-').unwrap_or(weekday);
+		let weekday = lookup_word(weekday, WEEKDAY_NAMES).ok_or(INVALID)?;
+		return Ok(RuleDay::AtMost(weekday as i64, DAYS_IN_MONTH_LEAP[month]));
+	}
+	let parse_day_of_month = |day: &str| match day.parse::<i64>() {
+		Ok(day) if (1..=DAYS_IN_MONTH_LEAP[month]).contains(&day) => Ok(day),
+		_ => Err(INVALID),
+	};
+	if let Some((weekday, day)) = day.split_once(">=") {
+		let weekday = lookup_word(weekday, WEEKDAY_NAMES).ok_or(INVALID)?;
+		return Ok(RuleDay::AtLeast(weekday as i64, parse_day_of_month(day)?));
+	}
+	if let Some((weekday, day)) = day.split_once("<=") {
+		let weekday = lookup_word(weekday, WEEKDAY_NAMES).ok_or(INVALID)?;
+		return Ok(RuleDay::AtMost(weekday as i64, parse_day_of_month(day)?));
+	}
+	Ok(RuleDay::Fixed(parse_day_of_month(day)?))
+}
+
+/// Parses `[-]hh[:mm[:ss[.frac]]][suffix]` into seconds and optional suffix letter.
+fn parse_time(time: &str) -> Result<(i64, Option<char>), &'static str> {
+	const INVALID: &str = "Invalid time";
+	let (time, suffix) = match time.chars().last() {
+		Some(c) if c.is_ascii_alphabetic() => (&time[..time.len() - 1], Some(c.to_ascii_lowercase())),
+		_ => (time, None),
+	};
+	if time == "-" {
+		return Ok((0, suffix));
+	}
+	let (sign, time) = match time.strip_prefix('-') {
+		Some(time) => (-1, time),
+		None => (1, time),
+	};
+	let mut parts = time.splitn(3, ':');
+	let hours: i64 = parts.next()
+		.and_then(|hours| hours.parse().ok())
+		.ok_or(INVALID)?;
+	let minutes: i64 = match parts.next() {
+		Some(minutes) => minutes.parse().ok().filter(|m| (0..60).contains(m)).ok_or(INVALID)?,
+		None => 0,
+	};
+	let seconds = match parts.next() {
+		Some(seconds) => seconds.parse::<f64>().ok()
+			.filter(|s| (0.0..61.0).contains(s))
+			.ok_or(INVALID)?
+			.round_ties_even() as i64,
+		None => 0,
+	};
+	Ok((sign * (hours * SECS_PER_HOUR + minutes * 60 + seconds), suffix))
+}
+
+/// Parses saved amount, returning it together with whether it's daylight saving time.
+fn parse_save(save: &str) -> Result<(i64, bool), &'static str> {
+	let (save, suffix) = parse_time(save)?;
+	match suffix {
+		None => Ok((save, save != 0)),
+		Some('s') => Ok((save, false)),
+		Some('d') => Ok((save, true)),
+		_ => Err("Invalid saved time"),
+	}
+}
+
+/// Mirrors `doabbr` of `zic`, quoting abbreviations which aren't purely alphabetic.
+fn format_abbr(format: &str, std_offset: i64, letters: Option<&str>, is_dst: bool, save: i64) -> String {
+	let abbr = if let Some((std, dst)) = format.split_once('/') {
+		if is_dst { dst } else { std }.to_owned()
+	} else if format.contains("%z") {
+		format.replacen("%z", &format_numeric_abbr(std_offset + save), 1)
+	} else {
+		format.replacen("%s", letters.unwrap_or("%s"), 1)
+	};
+	if !abbr.is_empty() && abbr.bytes().all(|c| c.is_ascii_alphabetic()) {
+		abbr
+	} else {
+		format!("<{abbr}>")
+	}
+}
+
+/// Formats UT offset as `%z` abbreviation, e.g. `+03` or `-0930`.
+fn format_numeric_abbr(offset: i64) -> String {
+	let sign = if offset < 0 { '-' } else { '+' };
+	let offset = offset.abs();
+	let (hours, minutes, seconds) = (offset / SECS_PER_HOUR, offset / 60 % 60, offset % 60);
+	if seconds != 0 {
+		format!("{sign}{hours:02}{minutes:02}{seconds:02}")
+	} else if minutes != 0 {
+		format!("{sign}{hours:02}{minutes:02}")
+	} else {
+		format!("{sign}{hours:02}")
+	}
+}
+
+/// Appends POSIX offset or time of day, failing if out of representable range.
+fn push_offset(out: &mut String, offset: i64) -> Option<()> {
+	if offset < 0 {
+		out.push('-');
+	}
+	let offset = offset.abs();
+	let (hours, minutes, seconds) = (offset / SECS_PER_HOUR, offset / 60 % 60, offset % 60);
+	if hours >= 24 * DAYS_PER_WEEK {
+		return None;
+	}
+	out.push_str(&hours.to_string());
+	if minutes != 0 || seconds != 0 {
+		out.push_str(&format!(":{minutes:02}"));
+		if seconds != 0 {
+			out.push_str(&format!(":{seconds:02}"));
+		}
+	}
+	Some(())
+}
+
+/// Mirrors `stringrule` of `zic`, appending transition date and local time of rule.
+fn push_rule(out: &mut String, rule: &Rule, save: i64, std_offset: i64) -> Option<()> {
+	let mut time = rule.time;
+	let (weekday, week, weekday_offset) = match rule.day {
+		RuleDay::Fixed(day) => {
+			if rule.month == 1 && day == 29 {
+				return None;
+			}
+			let total: i64 = DAYS_IN_MONTH[..rule.month].iter().sum();
+			if rule.month <= 1 {
+				out.push_str(&(total + day - 1).to_string());
+			} else {
+				out.push_str(&format!("J{}", total + day));
+			}
+			(None, 0, 0)
+		}
+		RuleDay::AtLeast(weekday, day) => {
+			(Some(weekday), 1 + (day - 1) / DAYS_PER_WEEK, (day - 1) % DAYS_PER_WEEK)
+		}
+		RuleDay::AtMost(weekday, day) if day == DAYS_IN_MONTH_LEAP[rule.month] => {
+			(Some(weekday), 5, 0)
+		}
+		RuleDay::AtMost(weekday, day) => {
+			(Some(weekday), day / DAYS_PER_WEEK, day % DAYS_PER_WEEK)
+		}
+	};
+	if let Some(weekday) = weekday {
+		// Weekday on or after a day that isn't start of week is shifted
+		// to the matching week, moving the time of day by whole days
+		let weekday = (weekday - weekday_offset).rem_euclid(DAYS_PER_WEEK);
+		time += weekday_offset * SECS_PER_DAY;
+		out.push_str(&format!("M{}.{week}.{weekday}", rule.month + 1));
+	}
+	if rule.time_ut {
+		time += std_offset;
+	}
+	if rule.time_std && !rule.is_dst {
+		time += save;
+	}
+	if time != 2 * SECS_PER_HOUR {
+		out.push('/');
+		push_offset(out, time)?;
+	}
+	Some(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	
+	const RULES: &str = "
+# Rule	NAME	FROM	TO	-	IN	ON	AT	SAVE	LETTER/S
+Rule	US	1967	2006	-	Oct	lastSun	2:00	0	S
+Rule	US	1967	1973	-	Apr	lastSun	2:00	1:00	D
+Rule	US	1987	2006	-	Apr	Sun>=1	2:00	1:00	D
+Rule	US	2007	max	-	Mar	Sun>=8	2:00	1:00	D
+Rule	US	2007	max	-	Nov	Sun>=1	2:00	0	S
+Rule	EU	1981	max	-	Mar	lastSun	 1:00u	1:00	S
+Rule	EU	1996	max	-	Oct	lastSun	 1:00u	0	-
+Rule	Eire	1971	only	-	Oct	31	 2:00u	-1:00	-
+Rule	Eire	1972	1980	-	Mar	Sun>=16	 2:00u	0	-
+Rule	Eire	1981	max	-	Mar	lastSun	 1:00u	0	-
+Rule	Eire	1996	max	-	Oct	lastSun	 1:00u	-1:00	-
+Rule	LH	2008	max	-	Apr	Sun>=1	2:00	0	-
+Rule	LH	2008	max	-	Oct	Sun>=1	2:00	0:30	-
+Rule	Brazil	2016	2019	-	Feb	Sun>=15	0:00	0	-
+Rule	Brazil	2018	only	-	Nov	Sun>=1	0:00	1:00	-
+";
+	
+	fn compile(source: &str) -> TzData {
+		let mut compiler = TzDataCompiler::default();
+		compiler.parse("rules", RULES).expect("Invalid rules");
+		compiler.parse("zones", source).expect("Invalid zones");
+		compiler.finish()
+	}
+	
+	fn footer(source: &str, zone: &str) -> Option<String> {
+		compile(source).zones.get(zone).cloned()
+	}
+	
+	fn syntax_error(source: &str) -> (usize, &'static str) {
+		match TzDataCompiler::default().parse("zones", source) {
+			Err(TzDataError::Syntax { file, line, reason }) => {
+				assert_eq!(file, "zones");
+				(line, reason)
+			}
+			res => panic!("Unexpected result {res:?}"),
+		}
+	}
+	
+	#[test]
+	fn compiles_zic_footers() {
+		let source = "
+Zone America/New_York	-4:56:02 -	LMT	1883 Nov 18 17:00u
+			-5:00	US	E%sT
+Zone	Europe/London	-0:01:15 -	LMT	1847 Dec  1
+			 0:00	EU	GMT/BST
+Zone	Europe/Berlin	0:53:28 -	LMT	1893 Apr
+			 1:00	EU	CE%sT
+Zone	Europe/Dublin	-0:25:21 -	LMT	1880 Aug  2
+			 1:00	Eire	IST/GMT
+Zone Australia/Lord_Howe 10:36:20 -	LMT	1895 Feb
+			10:30	LH	%z
+Zone America/Sao_Paulo	-3:06:28 -	LMT	1914
+			-3:00	Brazil	%z
+Zone	Asia/Kolkata	5:53:28 -	LMT	1854 Jun 28 # Kolkata
+			5:30	-	IST
+Zone	Asia/Kathmandu	5:41:16 -	LMT	1920
+			5:45	-	%z
+Zone	Etc/UTC		0	-	UTC
+";
+		let expected = [
+			("America/New_York", "EST5EDT,M3.2.0,M11.1.0"),
+			("Europe/London", "GMT0BST,M3.5.0/1,M10.5.0"),
+			("Europe/Berlin", "CET-1CEST,M3.5.0,M10.5.0/3"),
+			("Europe/Dublin", "IST-1GMT0,M10.5.0,M3.5.0/1"),
+			("Australia/Lord_Howe", "<+1030>-10:30<+11>-11,M10.1.0,M4.1.0"),
+			("America/Sao_Paulo", "<-03>3"),
+			("Asia/Kolkata", "IST-5:30"),
+			("Asia/Kathmandu", "<+0545>-5:45"),
+			("Etc/UTC", "UTC0"),
+		];
+		let data = compile(source);
+		for (zone, posix) in expected {
+			assert_eq!(data.zones.get(zone).map(String::as_str), Some(posix), "{zone}");
+		}
+		assert_eq!(data.zones.len(), expected.len());
+	}
+	
+	#[test]
+	fn compiles_permanent_dst() {
+		// Expressed like zic does, as DST from January 1 to the end of year
+		assert_eq!(
+			footer("Zone Test/Perm -5:00 1:00 EDT", "Test/Perm").as_deref(),
+			Some("XXX3EDT4,0/0,J365/23"),
+		);
+	}
+	
+	#[test]
+	fn omits_ambiguous_rules() {
+		let source = "
+Rule	Twice	2000	max	-	Mar	lastSun	2:00	1:00	S
+Rule	Twice	2000	max	-	Apr	lastSun	2:00	1:00	S
+Zone	Test/Twice	1:00	Twice	T%sT
+Zone	Test/Missing	1:00	Missing	T%sT
+";
+		let data = compile(source);
+		assert_eq!(data.zones.get("Test/Twice"), None);
+		assert_eq!(data.zones.get("Test/Missing"), None);
+	}
+	
+	#[test]
+	fn resolves_links() {
+		let source = "
+Zone	Etc/UTC		0	-	UTC
+Link	Etc/UTC		Etc/Universal
+Link	Etc/Universal	Etc/Zulu
+Link	Etc/Missing	Etc/Broken
+Link	Etc/Loop	Etc/Loop
+";
+		let data = compile(source);
+		assert_eq!(data.zones.get("Etc/Universal").map(String::as_str), Some("UTC0"));
+		assert_eq!(data.zones.get("Etc/Zulu").map(String::as_str), Some("UTC0"));
+		assert_eq!(data.links.get("Etc/Universal").map(String::as_str), Some("Etc/UTC"));
+		assert_eq!(data.links.get("Etc/Zulu").map(String::as_str), Some("Etc/UTC"));
+		assert!(!data.zones.contains_key("Etc/Broken"));
+		assert!(!data.links.contains_key("Etc/Broken"));
+		assert!(!data.links.contains_key("Etc/Loop"));
+	}
+	
+	#[test]
+	fn keeps_only_last_zone_line() {
+		let source = "
+Zone	Test/Zone	1:00	-	CET	1990 Jan 1
+			2:00	-	EET	2000
+			3:00	-	%z
+";
+		assert_eq!(footer(source, "Test/Zone").as_deref(), Some("<+03>-3"));
+	}
+	
+	#[test]
+	fn reports_syntax_errors() {
+		assert_eq!(syntax_error("Foo bar"), (1, "Unknown line keyword"));
+		assert_eq!(syntax_error("\n\nZone"), (3, "Missing zone name"));
+		assert_eq!(syntax_error("Zone A 1:00 -"), (1, "Wrong number of fields on zone line"));
+		assert_eq!(
+			syntax_error("Zone A 1:00 - CET 1990\n# comment\n"),
+			(2, "Missing zone continuation line"),
+		);
+		assert_eq!(syntax_error("Zone A x - CET"), (1, "Invalid time"));
+		assert_eq!(syntax_error("Link A"), (1, "Wrong number of fields on link line"));
+		assert_eq!(syntax_error("Rule A 2000 max - Mar"), (1, "Wrong number of fields on rule line"));
+		assert_eq!(syntax_error("Rule A x max - Mar 1 2:00 1:00 S"), (1, "Invalid starting year"));
+		assert_eq!(syntax_error("Rule A 2000 x - Mar 1 2:00 1:00 S"), (1, "Invalid ending year"));
+		assert_eq!(syntax_error("Rule A 2000 max - Ju 1 2:00 1:00 S"), (1, "Invalid month name"));
+		assert_eq!(syntax_error("Rule A 2000 max - Feb 30 2:00 1:00 S"), (1, "Invalid day of month"));
+		assert_eq!(syntax_error("Rule A 2000 max - Feb lastFoo 2:00 1:00 S"), (1, "Invalid day of month"));
+		assert_eq!(syntax_error("Rule A 2000 max - Mar 1 2:00x 1:00 S"), (1, "Invalid time of day"));
+		assert_eq!(syntax_error("Rule A 2000 max - Mar 1 2:60 1:00 S"), (1, "Invalid time"));
+		assert_eq!(syntax_error("Rule A 2000 max - Mar 1 2:00 1:00u S"), (1, "Invalid saved time"));
+	}
+	
+	#[test]
+	fn parses_fields() {
+		assert_eq!(parse_time("2:00"), Ok((7200, None)));
+		assert_eq!(parse_time("-0:25:21"), Ok((-1521, None)));
+		assert_eq!(parse_time("24:00"), Ok((86400, None)));
+		assert_eq!(parse_time("1:00U"), Ok((3600, Some('u'))));
+		assert_eq!(parse_time("0:00:30.5"), Ok((30, None)));
+		assert_eq!(parse_time("-"), Ok((0, None)));
+		assert_eq!(parse_save("1:00s"), Ok((3600, false)));
+		assert_eq!(parse_save("0d"), Ok((0, true)));
+		assert_eq!(parse_save("-1:00"), Ok((-3600, true)));
+		assert_eq!(lookup_word("ja", MONTH_NAMES), Some(0));
+		assert_eq!(lookup_word("Ju", MONTH_NAMES), None);
+		assert_eq!(lookup_word("max", &["minimum", "maximum"]), Some(1));
+		assert!(matches!(parse_day("lastSun", 1), Ok(RuleDay::AtMost(0, 29))));
+		assert!(matches!(parse_day("Sat<=30", 8), Ok(RuleDay::AtMost(6, 30))));
+		assert!(matches!(parse_day("Sun>=8", 2), Ok(RuleDay::AtLeast(0, 8))));
+		assert!(parse_day("Sun>=32", 2).is_err());
+	}
+	
+	#[test]
+	fn compiles_directory() {
+		let dir = tempfile::tempdir().expect("Unable to create directory");
+		std::fs::write(dir.path().join("northamerica"), RULES).expect("Unable to write file");
+		std::fs::write(dir.path().join("backward"), "Link America/New_York US/Eastern\n")
+			.expect("Unable to write file");
+		std::fs::write(dir.path().join("zones"), "Zone America/New_York -5:00 US E%sT\n")
+			.expect("Unable to write file");
+		let data = TzDataCompiler::compile_dir(dir.path(), &["northamerica", "zones", "backward"])
+			.expect("Unable to compile");
+		assert_eq!(
+			data.zones.get("US/Eastern").map(String::as_str),
+			Some("EST5EDT,M3.2.0,M11.1.0"),
+		);
+		let err = TzDataCompiler::compile_dir(dir.path(), &["missing"]);
+		assert!(matches!(err, Err(TzDataError::Io(_))));
+	}
+}
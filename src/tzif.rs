@@ -0,0 +1,598 @@
+use chrono::{DateTime, Datelike, NaiveDate, Weekday};
+
+const SECS_PER_HOUR: i64 = 60 * 60;
+const SECS_PER_DAY: i64 = 24 * SECS_PER_HOUR;
+const TZIF_HEADER_LEN: usize = 44;
+/// Transitions of POSIX rule repeat every 400 years.
+const MAX_RULE_YEARS: i32 = 400;
+
+/// Local time type in effect between transitions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocalTimeType {
+	/// Seconds east of UTC.
+	pub utc_offset: i32,
+	pub dst: bool,
+	pub abbreviation: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PosixDate {
+	/// `Jn`, day of year 1-365 never counting February 29.
+	Julian(i64),
+	/// `n`, zero-based day of year counting February 29.
+	Day(i64),
+	/// `Mm.w.d`, weekday `d` of week `w` (5 being the last) of month `m`.
+	Month(u32, u32, u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PosixTransition {
+	date: PosixDate,
+	/// Local time of day in seconds, may be negative or exceed 24 hours.
+	time: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PosixDst {
+	local: LocalTimeType,
+	start: PosixTransition,
+	end: PosixTransition,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PosixTz {
+	std: LocalTimeType,
+	dst: Option<PosixDst>,
+}
+
+/// Timezone loaded from TZif file or bare POSIX TZ string.
+/// Transitions listed in TZif data take precedence, POSIX TZ string
+/// describes transitions after the last listed one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TimeZone {
+	posix: String,
+	rule: PosixTz,
+	transitions: Vec<(i64, usize)>,
+	types: Vec<LocalTimeType>,
+}
+
+impl TimeZone {
+	/// Parses TZif (version 2 or later) data, `None` if it's invalid or has no POSIX TZ footer.
+	pub fn from_tzif(bytes: &[u8]) -> Option<Self> {
+		let mut bytes = bytes;
+		let (version, counts) = read_tzif_header(&mut bytes)?;
+		if version < b'2' {
+			return None;
+		}
+		let [is_ut_count, is_std_count, leap_count, time_count, type_count, char_count] = counts;
+		take(&mut bytes, time_count * 5 + type_count * 6 + char_count + leap_count * 8
+			+ is_std_count + is_ut_count)?;
+		let (_, counts) = read_tzif_header(&mut bytes)?;
+		let [is_ut_count, is_std_count, leap_count, time_count, type_count, char_count] = counts;
+		let times = take(&mut bytes, time_count * 8)?;
+		let indices = take(&mut bytes, time_count)?;
+		let type_records = take(&mut bytes, type_count * 6)?;
+		let chars = take(&mut bytes, char_count)?;
+		let leap_records = take(&mut bytes, leap_count * 12)?;
+		take(&mut bytes, is_std_count + is_ut_count)?;
+		let footer = bytes.strip_prefix(b"\n")?.strip_suffix(b"\n")?;
+		let posix = str::from_utf8(footer).ok()?.trim();
+		if posix.is_empty() {
+			return None;
+		}
+		let mut types = Vec::with_capacity(type_count);
+		for record in type_records.chunks_exact(6) {
+			let abbreviation = chars.get(record[5] as usize..)?;
+			let len = abbreviation.iter().position(|c| *c == 0)?;
+			types.push(LocalTimeType {
+				utc_offset: i32::from_be_bytes(record[..4].try_into().ok()?),
+				dst: record[4] != 0,
+				abbreviation: String::from_utf8_lossy(&abbreviation[..len]).into_owned(),
+			});
+		}
+		let leaps: Vec<(i64, i64)> = leap_records.chunks_exact(12)
+			.map(|record| (
+				i64::from_be_bytes(record[..8].try_into().unwrap_or_default()),
+				i32::from_be_bytes(record[8..].try_into().unwrap_or_default()) as i64,
+			))
+			.collect();
+		let mut transitions = Vec::with_capacity(time_count);
+		for (time, index) in times.chunks_exact(8).zip(indices) {
+			let time = i64::from_be_bytes(time.try_into().ok()?);
+			// Files compiled with leap seconds count them in transition times
+			let correction = leaps.iter()
+				.rev()
+				.find(|(occurrence, _)| *occurrence <= time)
+				.map_or(0, |(_, correction)| *correction);
+			let index = *index as usize;
+			if index >= types.len() {
+				return None;
+			}
+			transitions.push((time - correction, index));
+		}
+		let mut tz = Self::from_posix(posix.to_owned())?;
+		tz.transitions = transitions;
+		tz.types = types;
+		Some(tz)
+	}
+	
+	/// Parses POSIX TZ string, e.g. `EST5EDT,M3.2.0,M11.1.0`.
+	pub fn from_posix(posix: String) -> Option<Self> {
+		let rule = PosixTz::parse(&posix)?;
+		Some(Self {
+			posix,
+			rule,
+			transitions: Vec::new(),
+			types: Vec::new(),
+		})
+	}
+	
+	pub fn posix(&self) -> &str {
+		&self.posix
+	}
+	
+	/// Local time type in effect at given Unix timestamp.
+	pub fn local_time_type(&self, at: i64) -> &LocalTimeType {
+		match self.transitions.last() {
+			Some((last, _)) if at < *last => {
+				let i = self.transitions.partition_point(|(time, _)| *time <= at);
+				match i.checked_sub(1) {
+					Some(i) => &self.types[self.transitions[i].1],
+					None => &self.types[0],
+				}
+			}
+			_ => self.rule.local_time_type(at),
+		}
+	}
+	
+	/// Up to `count` transitions strictly after given Unix timestamp,
+	/// skipping ones which don't change local time type.
+	pub fn next_transitions(&self, at: i64, count: usize) -> Vec<(i64, &LocalTimeType)> {
+		let mut out = Vec::with_capacity(count);
+		let mut current = self.local_time_type(at);
+		let listed = self.transitions.iter()
+			.filter(|(time, _)| *time > at)
+			.map(|(time, i)| (*time, &self.types[*i]));
+		let after = self.transitions.last().map_or(at, |(last, _)| at.max(*last));
+		let mut transitions = listed.chain(self.rule.transitions_after(after)).peekable();
+		while let Some((time, local)) = transitions.next() {
+			if out.len() >= count {
+				break;
+			}
+			// Simultaneous transitions take effect as the last one
+			if transitions.peek().is_some_and(|(next, _)| *next == time) {
+				continue;
+			}
+			if local == current {
+				continue;
+			}
+			out.push((time, local));
+			current = local;
+		}
+		out
+	}
+}
+
+impl PosixTz {
+	fn parse(posix: &str) -> Option<Self> {
+		let mut s = posix;
+		let std_abbr = parse_abbreviation(&mut s)?;
+		let std_offset = -parse_hms(&mut s)?;
+		let std = LocalTimeType {
+			utc_offset: std_offset.try_into().ok()?,
+			dst: false,
+			abbreviation: std_abbr,
+		};
+		if s.is_empty() {
+			return Some(Self { std, dst: None });
+		}
+		let dst_abbr = parse_abbreviation(&mut s)?;
+		let dst_offset = if s.is_empty() || s.starts_with(',') {
+			std_offset + SECS_PER_HOUR
+		} else {
+			-parse_hms(&mut s)?
+		};
+		let local = LocalTimeType {
+			utc_offset: dst_offset.try_into().ok()?,
+			dst: true,
+			abbreviation: dst_abbr,
+		};
+		let (start, end) = if s.is_empty() {
+			// Implementation defined, US rules like most implementations
+			(
+				PosixTransition { date: PosixDate::Month(3, 2, 0), time: 2 * SECS_PER_HOUR },
+				PosixTransition { date: PosixDate::Month(11, 1, 0), time: 2 * SECS_PER_HOUR },
+			)
+		} else {
+			s = s.strip_prefix(',')?;
+			let start = parse_transition(&mut s)?;
+			s = s.strip_prefix(',')?;
+			let end = parse_transition(&mut s)?;
+			(start, end)
+		};
+		if !s.is_empty() {
+			return None;
+		}
+		Some(Self {
+			std,
+			dst: Some(PosixDst { local, start, end }),
+		})
+	}
+	
+	/// Transitions of given year in chronological order.
+	fn year_transitions(&self, year: i32) -> Vec<(i64, &LocalTimeType)> {
+		let Some(dst) = &self.dst else { return Vec::new() };
+		let (Some(start), Some(end)) = (
+			transition_date(dst.start.date, year),
+			transition_date(dst.end.date, year),
+		) else {
+			return Vec::new();
+		};
+		let start = start * SECS_PER_DAY + dst.start.time - self.std.utc_offset as i64;
+		let end = end * SECS_PER_DAY + dst.end.time - dst.local.utc_offset as i64;
+		let mut out = vec![(start, &dst.local), (end, &self.std)];
+		out.sort_by_key(|(time, _)| *time);
+		out
+	}
+	
+	fn local_time_type(&self, at: i64) -> &LocalTimeType {
+		let year = year_of(at);
+		// Previous year's transitions cover time before this year's first one
+		let mut current = &self.std;
+		for year in year - 1..=year + 1 {
+			for (time, local) in self.year_transitions(year) {
+				if time > at {
+					return current;
+				}
+				current = local;
+			}
+		}
+		current
+	}
+	
+	fn transitions_after(&self, at: i64) -> impl Iterator<Item = (i64, &LocalTimeType)> {
+		let year = year_of(at);
+		(year - 1..year + MAX_RULE_YEARS)
+			.flat_map(move |year| self.year_transitions(year))
+			.filter(move |(time, _)| *time > at)
+	}
+}
+
+fn read_tzif_header(bytes: &mut &[u8]) -> Option<(u8, [usize; 6])> {
+	let header = take(bytes, TZIF_HEADER_LEN)?;
+	if !header.starts_with(b"TZif") {
+		return None;
+	}
+	let mut counts = [0; 6];
+	for (count, chunk) in counts.iter_mut().zip(header[20..].chunks_exact(4)) {
+		*count = u32::from_be_bytes(chunk.try_into().ok()?) as usize;
+	}
+	Some((header[4], counts))
+}
+
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+	let (head, tail) = bytes.split_at_checked(len)?;
+	*bytes = tail;
+	Some(head)
+}
+
+fn parse_abbreviation(s: &mut &str) -> Option<String> {
+	if let Some(quoted) = s.strip_prefix('<') {
+		let len = quoted.find('>')?;
+		let abbr = quoted[..len].to_owned();
+		*s = &quoted[len + 1..];
+		return Some(abbr);
+	}
+	let len = s.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(s.len());
+	if len == 0 {
+		return None;
+	}
+	let abbr = s[..len].to_owned();
+	*s = &s[len..];
+	Some(abbr)
+}
+
+/// Parses `[+-]hh[:mm[:ss]]` into seconds.
+fn parse_hms(s: &mut &str) -> Option<i64> {
+	let sign = match s.as_bytes().first() {
+		Some(b'-') => -1,
+		Some(b'+') => 1,
+		_ => 0,
+	};
+	if sign != 0 {
+		*s = &s[1..];
+	}
+	let mut total = 0;
+	for (i, unit) in [SECS_PER_HOUR, 60, 1].into_iter().enumerate() {
+		if i > 0 {
+			match s.strip_prefix(':') {
+				Some(rest) => *s = rest,
+				None => break,
+			}
+		}
+		let len = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+		let value: i64 = s[..len].parse().ok()?;
+		*s = &s[len..];
+		total += value * unit;
+	}
+	Some(if sign < 0 { -total } else { total })
+}
+
+fn parse_number(s: &mut &str) -> Option<i64> {
+	let len = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+	let value = s[..len].parse().ok()?;
+	*s = &s[len..];
+	Some(value)
+}
+
+fn parse_transition(s: &mut &str) -> Option<PosixTransition> {
+	let date = if let Some(rest) = s.strip_prefix('J') {
+		*s = rest;
+		PosixDate::Julian(parse_number(s).filter(|day| (1..=365).contains(day))?)
+	} else if let Some(rest) = s.strip_prefix('M') {
+		*s = rest;
+		let month = parse_number(s).filter(|month| (1..=12).contains(month))?;
+		*s = s.strip_prefix('.')?;
+		let week = parse_number(s).filter(|week| (1..=5).contains(week))?;
+		*s = s.strip_prefix('.')?;
+		let weekday = parse_number(s).filter(|weekday| (0..=6).contains(weekday))?;
+		PosixDate::Month(month as u32, week as u32, weekday as u32)
+	} else {
+		PosixDate::Day(parse_number(s).filter(|day| (0..=365).contains(day))?)
+	};
+	let time = match s.strip_prefix('/') {
+		Some(rest) => {
+			*s = rest;
+			parse_hms(s)?
+		}
+		None => 2 * SECS_PER_HOUR,
+	};
+	Some(PosixTransition { date, time })
+}
+
+/// Days since Unix epoch of transition date in given year.
+fn transition_date(date: PosixDate, year: i32) -> Option<i64> {
+	let jan_1 = NaiveDate::from_ymd_opt(year, 1, 1)?;
+	let days = match date {
+		PosixDate::Julian(day) => {
+			let leap_day = jan_1.leap_year() && day >= 60;
+			jan_1.to_epoch_days() as i64 + day - 1 + leap_day as i64
+		}
+		PosixDate::Day(day) => jan_1.to_epoch_days() as i64 + day,
+		PosixDate::Month(month, week, weekday) => {
+			let weekday = Weekday::try_from(((weekday + 6) % 7) as u8).ok()?;
+			let date = NaiveDate::from_weekday_of_month_opt(year, month, weekday, week as u8)
+				.or_else(|| NaiveDate::from_weekday_of_month_opt(year, month, weekday, 4))?;
+			date.to_epoch_days() as i64
+		}
+	};
+	Some(days)
+}
+
+fn year_of(at: i64) -> i32 {
+	DateTime::from_timestamp(at, 0).map_or(1970, |date| date.year())
+}
+
+#[cfg(test)]
+mod tests {
+	use chrono::Utc;
+	use super::*;
+	
+	fn ts(at: &str) -> i64 {
+		at.parse::<DateTime<Utc>>().expect("Invalid timestamp").timestamp()
+	}
+	
+	fn posix(posix: &str) -> TimeZone {
+		TimeZone::from_posix(posix.to_owned()).expect("Invalid POSIX TZ string")
+	}
+	
+	fn local(utc_offset: i32, dst: bool, abbreviation: &str) -> LocalTimeType {
+		LocalTimeType { utc_offset, dst, abbreviation: abbreviation.to_owned() }
+	}
+	
+	fn transitions(tz: &TimeZone, at: &str, count: usize) -> Vec<(i64, LocalTimeType)> {
+		tz.next_transitions(ts(at), count)
+			.into_iter()
+			.map(|(time, local)| (time, local.clone()))
+			.collect()
+	}
+	
+	/// TZif v2 file with empty v1 block.
+	fn tzif(
+		transitions: &[(i64, u8)],
+		types: &[(i32, bool, &str)],
+		leaps: &[(i64, i32)],
+		footer: &str,
+	) -> Vec<u8> {
+		let mut chars = Vec::new();
+		let mut type_records = Vec::new();
+		for (utc_offset, dst, abbreviation) in types {
+			type_records.extend(utc_offset.to_be_bytes());
+			type_records.extend([*dst as u8, chars.len() as u8]);
+			chars.extend(abbreviation.as_bytes());
+			chars.push(0);
+		}
+		let header = |counts: [usize; 6]| {
+			let mut header = b"TZif2".to_vec();
+			header.resize(20, 0);
+			for count in counts {
+				header.extend((count as u32).to_be_bytes());
+			}
+			header
+		};
+		let mut out = header([0, 0, 0, 0, 1, 1]);
+		out.extend([0; 6]);
+		out.push(0);
+		out.extend(header([0, 0, leaps.len(), transitions.len(), types.len(), chars.len()]));
+		for (time, _) in transitions {
+			out.extend(time.to_be_bytes());
+		}
+		out.extend(transitions.iter().map(|(_, index)| *index));
+		out.extend(type_records);
+		out.extend(chars);
+		for (occurrence, correction) in leaps {
+			out.extend(occurrence.to_be_bytes());
+			out.extend(correction.to_be_bytes());
+		}
+		out.extend(format!("\n{footer}\n").as_bytes());
+		out
+	}
+	
+	#[test]
+	fn evaluates_us_rule() {
+		let tz = posix("EST5EDT,M3.2.0,M11.1.0");
+		assert_eq!(tz.local_time_type(ts("2024-01-15T12:00:00Z")), &local(-18000, false, "EST"));
+		assert_eq!(tz.local_time_type(ts("2024-07-01T12:00:00Z")), &local(-14400, true, "EDT"));
+		assert_eq!(tz.local_time_type(ts("2024-03-10T06:59:59Z")).abbreviation, "EST");
+		assert_eq!(tz.local_time_type(ts("2024-03-10T07:00:00Z")).abbreviation, "EDT");
+		assert_eq!(transitions(&tz, "2024-01-01T00:00:00Z", 3), [
+			(ts("2024-03-10T07:00:00Z"), local(-14400, true, "EDT")),
+			(ts("2024-11-03T06:00:00Z"), local(-18000, false, "EST")),
+			(ts("2025-03-09T07:00:00Z"), local(-14400, true, "EDT")),
+		]);
+	}
+	
+	#[test]
+	fn evaluates_negative_dst_rule() {
+		// Europe/Dublin, standard time is in summer and winter time is "daylight saving"
+		let tz = posix("IST-1GMT0,M10.5.0,M3.5.0/1");
+		assert_eq!(tz.local_time_type(ts("2024-01-15T12:00:00Z")), &local(0, true, "GMT"));
+		assert_eq!(tz.local_time_type(ts("2024-07-01T12:00:00Z")), &local(3600, false, "IST"));
+		assert_eq!(transitions(&tz, "2024-01-01T00:00:00Z", 2), [
+			(ts("2024-03-31T01:00:00Z"), local(3600, false, "IST")),
+			(ts("2024-10-27T01:00:00Z"), local(0, true, "GMT")),
+		]);
+	}
+	
+	#[test]
+	fn evaluates_southern_hemisphere_rule() {
+		// Australia/Lord_Howe, half hour DST spanning new year
+		let tz = posix("<+1030>-10:30<+11>-11,M10.1.0,M4.1.0");
+		assert_eq!(tz.local_time_type(ts("2024-01-15T12:00:00Z")), &local(39600, true, "+11"));
+		assert_eq!(tz.local_time_type(ts("2024-07-01T12:00:00Z")), &local(37800, false, "+1030"));
+		assert_eq!(transitions(&tz, "2024-01-01T00:00:00Z", 2), [
+			(ts("2024-04-06T15:00:00Z"), local(37800, false, "+1030")),
+			(ts("2024-10-05T15:30:00Z"), local(39600, true, "+11")),
+		]);
+	}
+	
+	#[test]
+	fn evaluates_negative_transition_times() {
+		// America/Nuuk before 2023
+		let tz = posix("<-03>3<-02>,M3.5.0/-2,M10.5.0/-1");
+		assert_eq!(transitions(&tz, "2022-01-01T00:00:00Z", 2), [
+			(ts("2022-03-27T01:00:00Z"), local(-7200, true, "-02")),
+			(ts("2022-10-30T01:00:00Z"), local(-10800, false, "-03")),
+		]);
+	}
+	
+	#[test]
+	fn evaluates_rule_without_dst() {
+		let tz = posix("<+0530>-5:30");
+		assert_eq!(tz.local_time_type(ts("2024-07-01T12:00:00Z")), &local(19800, false, "+0530"));
+		assert!(tz.next_transitions(ts("2024-01-01T00:00:00Z"), 2).is_empty());
+		let tz = posix("<-0330>+3:30:15");
+		assert_eq!(tz.local_time_type(0).utc_offset, -12615);
+	}
+	
+	#[test]
+	fn computes_day_of_year_dates() {
+		let days = |year, month, day| {
+			NaiveDate::from_ymd_opt(year, month, day).expect("Invalid date").to_epoch_days() as i64
+		};
+		// Julian days never count February 29, zero-based days do
+		assert_eq!(transition_date(PosixDate::Julian(60), 2024), Some(days(2024, 3, 1)));
+		assert_eq!(transition_date(PosixDate::Julian(60), 2023), Some(days(2023, 3, 1)));
+		assert_eq!(transition_date(PosixDate::Day(59), 2024), Some(days(2024, 2, 29)));
+		assert_eq!(transition_date(PosixDate::Day(59), 2023), Some(days(2023, 3, 1)));
+		// Fifth week means the last one
+		assert_eq!(transition_date(PosixDate::Month(2, 5, 4), 2024), Some(days(2024, 2, 29)));
+		assert_eq!(transition_date(PosixDate::Month(2, 5, 4), 2023), Some(days(2023, 2, 23)));
+	}
+	
+	#[test]
+	fn rejects_malformed_posix() {
+		let invalid = [
+			"",
+			"EST",
+			"5EDT",
+			"<EST5",
+			"EST5EDT,M3.2.0",
+			"EST5EDT,M3.2.0,",
+			"EST5EDT,M13.2.0,M11.1.0",
+			"EST5EDT,M3.6.0,M11.1.0",
+			"EST5EDT,M3.2.7,M11.1.0",
+			"EST5EDT,J0,J365",
+			"EST5EDT,0,366",
+			"EST5EDT,M3.2.0/x,M11.1.0",
+			"EST5EDT,M3.2.0,M11.1.0,M12.1.0",
+			"EST999999999999",
+		];
+		for posix in invalid {
+			assert_eq!(PosixTz::parse(posix), None, "{posix}");
+		}
+	}
+	
+	#[test]
+	fn parses_tzif() {
+		let bytes = tzif(
+			&[(-100, 1), (0, 2), (100, 1)],
+			&[(-17762, false, "LMT"), (-18000, false, "EST"), (-14400, true, "EDT")],
+			&[],
+			"EST5EDT,M3.2.0,M11.1.0",
+		);
+		let tz = TimeZone::from_tzif(&bytes).expect("Invalid TZif");
+		assert_eq!(tz.posix(), "EST5EDT,M3.2.0,M11.1.0");
+		assert_eq!(tz.local_time_type(-101), &local(-17762, false, "LMT"));
+		assert_eq!(tz.local_time_type(-100), &local(-18000, false, "EST"));
+		assert_eq!(tz.local_time_type(50), &local(-14400, true, "EDT"));
+		// Footer rule applies after the last listed transition
+		assert_eq!(tz.local_time_type(ts("1970-07-01T00:00:00Z")).abbreviation, "EDT");
+		assert_eq!(transitions(&tz, "1969-12-31T23:59:00Z", 3), [
+			(0, local(-14400, true, "EDT")),
+			(100, local(-18000, false, "EST")),
+			(ts("1970-03-08T07:00:00Z"), local(-14400, true, "EDT")),
+		]);
+	}
+	
+	#[test]
+	fn subtracts_leap_seconds_from_tzif_transitions() {
+		let bytes = tzif(
+			&[(1000, 1), (2002, 0)],
+			&[(0, false, "UTC"), (3600, false, "CET")],
+			&[(500, 1), (1500, 2)],
+			"UTC0",
+		);
+		let tz = TimeZone::from_tzif(&bytes).expect("Invalid TZif");
+		assert_eq!(tz.transitions, [(999, 1), (2000, 0)]);
+	}
+	
+	#[test]
+	fn rejects_invalid_tzif() {
+		let types = [(0, false, "UTC")];
+		let valid = tzif(&[(0, 0)], &types, &[], "UTC0");
+		assert!(TimeZone::from_tzif(&valid).is_some());
+		// Version 1 has no footer
+		let mut v1 = valid.clone();
+		v1[4] = 0;
+		assert_eq!(TimeZone::from_tzif(&v1), None);
+		let mut bad_magic = valid.clone();
+		bad_magic[0] = b'X';
+		assert_eq!(TimeZone::from_tzif(&bad_magic), None);
+		assert_eq!(TimeZone::from_tzif(&valid[..valid.len() - 10]), None);
+		assert_eq!(TimeZone::from_tzif(&valid[..valid.len() - 1]), None);
+		assert_eq!(TimeZone::from_tzif(&tzif(&[(0, 0)], &types, &[], "")), None);
+		assert_eq!(TimeZone::from_tzif(&tzif(&[(0, 0)], &types, &[], "UTC")), None);
+		assert_eq!(TimeZone::from_tzif(&tzif(&[(0, 1)], &types, &[], "UTC0")), None);
+		assert_eq!(TimeZone::from_tzif(b""), None);
+	}
+	
+	#[test]
+	fn parses_system_tzif() {
+		let Ok(bytes) = std::fs::read("/usr/share/zoneinfo/America/New_York") else { return };
+		let tz = TimeZone::from_tzif(&bytes).expect("Invalid TZif");
+		assert_eq!(tz.posix(), "EST5EDT,M3.2.0,M11.1.0");
+		assert_eq!(tz.local_time_type(ts("1944-07-01T00:00:00Z")).abbreviation, "EWT");
+		assert_eq!(tz.local_time_type(ts("2024-07-01T00:00:00Z")), &local(-14400, true, "EDT"));
+	}
+}
@@ -0,0 +1,93 @@
+export type CaptchaProvider = "recaptcha" | "turnstile" | "hcaptcha";
+
+const CAPTCHA_ACTION = "submit";
+
+interface TurnstileApi {
+	render(container: HTMLElement, params: {
+		sitekey: string;
+		action?: string;
+		appearance?: "always" | "execute" | "interaction-only";
+		callback?: (token: string) => void;
+		"error-callback"?: (error: string) => void;
+	}): string;
+	remove(widgetId: string): void;
+}
+
+interface HCaptchaApi {
+	render(container: HTMLElement, params: {sitekey: string; size?: "invisible"}): string;
+	execute(widgetId: string, options: {async: true}): Promise<{response: string}>;
+	reset(widgetId: string): void;
+}
+
+declare global {
+	interface Window {
+		turnstile?: TurnstileApi;
+		hcaptcha?: HCaptchaApi;
+	}
+}
+
+const SCRIPT_URLS: Record<CaptchaProvider, (siteKey: string) => string> = {
+	recaptcha: siteKey => `https://www.google.com/recaptcha/api.js?render=${siteKey}`,
+	turnstile: () => "https://challenges.cloudflare.com/turnstile/v0/api.js?render=explicit",
+	hcaptcha: () => "https://js.hcaptcha.com/1/api.js?render=explicit",
+};
+
+let turnstileWidgetId: string | undefined;
+let hcaptchaWidgetId: string | undefined;
+
+export function loadCaptcha(provider: CaptchaProvider, siteKey: string) {
+	if (document.querySelector("script[id=captcha]")) return;
+	const script = document.createElement("script");
+	script.id = "captcha";
+	script.src = SCRIPT_URLS[provider](siteKey);
+	document.body.appendChild(script);
+}
+
+export async function executeCaptcha(provider: CaptchaProvider, siteKey: string): Promise<string> {
+	switch (provider) {
+		case "recaptcha":
+			return await grecaptcha.execute(siteKey, {action: CAPTCHA_ACTION});
+		case "turnstile":
+			return await executeTurnstile(siteKey);
+		case "hcaptcha":
+			return await executeHCaptcha(siteKey);
+	}
+}
+
+function widgetContainer(): HTMLElement {
+	let container = document.getElementById("captcha-widget");
+	if (!container) {
+		container = document.createElement("div");
+		container.id = "captcha-widget";
+		document.body.appendChild(container);
+	}
+	return container;
+}
+
+function executeTurnstile(siteKey: string): Promise<string> {
+	const turnstile = window.turnstile;
+	if (!turnstile) throw new Error("Turnstile isn't loaded yet");
+	// Turnstile tokens are single-use, so widget is rendered anew for every token
+	if (turnstileWidgetId !== undefined) turnstile.remove(turnstileWidgetId);
+	return new Promise((resolve, reject) => {
+		turnstileWidgetId = turnstile.render(widgetContainer(), {
+			sitekey: siteKey,
+			action: CAPTCHA_ACTION,
+			appearance: "interaction-only",
+			callback: resolve,
+			"error-callback": error => reject(new Error(`Turnstile error ${error}`)),
+		});
+	});
+}
+
+async function executeHCaptcha(siteKey: string): Promise<string> {
+	const hcaptcha = window.hcaptcha;
+	if (!hcaptcha) throw new Error("hCaptcha isn't loaded yet");
+	if (hcaptchaWidgetId === undefined) {
+		hcaptchaWidgetId = hcaptcha.render(widgetContainer(), {sitekey: siteKey, size: "invisible"});
+	} else {
+		hcaptcha.reset(hcaptchaWidgetId);
+	}
+	const res = await hcaptcha.execute(hcaptchaWidgetId, {async: true});
+	return res.response;
+}
@@ -1,4 +1,4 @@
-import React, { useCallback, useEffect, useState } from "react";
+import React, { useCallback, useEffect, useRef, useState } from "react";
 import * as api from "../client";
 import { GeoIpForm } from "./GeoIpForm.tsx";
 import { GeoIpStatus } from "./GeoIpStatus.tsx";
@@ -6,13 +6,18 @@ import { useShowDialog } from "./DialogProvider.tsx";
 import { ApiKeyDialog } from "./ApiKeyDialog.tsx";
 import { client } from "../client/client.gen.ts";
 import { ConfirmDialog } from "./ConfirmDialog.tsx";
+import { CaptchaProvider, executeCaptcha, loadCaptcha } from "../captcha.ts";
 
 export interface PageCtx {
-	recaptcha_site_key?: string;
+	captcha_provider?: CaptchaProvider;
+	captcha_site_key?: string;
+	captcha_session_ttl?: number;
 	osm_tiles_url?: string;
 }
 
 const API_KEY_STORAGE_KEY = "geoip.api-key";
+/** Margin before session expiration when fresh token is requested anyway. */
+const CAPTCHA_SESSION_MARGIN_MS = 5000;
 
 export const App: React.FC = () => {
 	const showDialog = useShowDialog();
@@ -20,6 +25,7 @@ export const App: React.FC = () => {
 	const [status, setStatus] = useState<api.GeoIpStatus | null>(null);
 	const [authenticated, setAuthenticated] = useState(false);
 	const [pageCtx, setPageCtx] = useState<PageCtx | null>(null);
+	const captchaSessionExpires = useRef(0);
 	
 	useEffect(() => {
 		const apiKey = localStorage.getItem(API_KEY_STORAGE_KEY);
@@ -54,13 +60,8 @@ export const App: React.FC = () => {
 	
 	useEffect(() => {
 		if (!pageCtx) return;
-		if (!authenticated && pageCtx.recaptcha_site_key !== undefined) {
-			if (!document.querySelector("script[id=recaptcha]")) {
-				const script = document.createElement("script");
-				script.id = "recaptcha";
-				script.src = `https://www.google.com/recaptcha/api.js?render=${pageCtx.recaptcha_site_key}`;
-				document.body.appendChild(script);
-			}
+		if (!authenticated && pageCtx.captcha_site_key !== undefined) {
+			loadCaptcha(pageCtx.captcha_provider ?? "recaptcha", pageCtx.captcha_site_key);
 		}
 	}, [pageCtx, authenticated]);
 	
@@ -102,9 +103,17 @@ export const App: React.FC = () => {
 		setAuthenticated(false);
 	}, []);
 	
-	const recaptchaFn = useCallback(async () => {
-		if (authenticated || pageCtx?.recaptcha_site_key === undefined) return undefined;
-		return await grecaptcha.execute(pageCtx.recaptcha_site_key, {action: "submit"});
+	const captchaFn = useCallback(async (force?: boolean) => {
+		if (authenticated || pageCtx?.captcha_site_key === undefined) return undefined;
+		// Session cookie issued with previous token is still valid
+		if (!force && Date.now() < captchaSessionExpires.current) return undefined;
+		const token = await executeCaptcha(pageCtx.captcha_provider ?? "recaptcha", pageCtx.captcha_site_key);
+		if (pageCtx.captcha_session_ttl !== undefined) {
+			captchaSessionExpires.current = Date.now()
+				+ pageCtx.captcha_session_ttl * 1000
+				- CAPTCHA_SESSION_MARGIN_MS;
+		}
+		return token;
 	}, [pageCtx, authenticated]);
 	
 	return (
@@ -119,7 +128,7 @@ export const App: React.FC = () => {
 			</div>)}
 			<GeoIpForm
 				databases={status?.databases ?? []}
-				recaptchaFn={recaptchaFn}
+				captchaFn={captchaFn}
 				osmTilesUrl={pageCtx?.osm_tiles_url ?? undefined}
 			/>
 			{status && <GeoIpStatus status={status} />}
@@ -14,13 +14,14 @@ interface GeoIpFormData {
 
 export interface GeoIpFormProps {
 	databases: api.GeoIpDatabaseStatus[];
-	recaptchaFn?: () => Promise<string | undefined>;
+	/** Returns captcha token, or nothing if session cookie is expected to be valid unless `force` is set. */
+	captchaFn?: (force?: boolean) => Promise<string | undefined>;
 	osmTilesUrl?: string;
 }
 
 export const GeoIpForm: React.FC<GeoIpFormProps> = ({
 	databases,
-	recaptchaFn,
+	captchaFn,
 	osmTilesUrl,
 }) => {
 	const {register, handleSubmit, setValue, getValues} = useForm<GeoIpFormData>();
@@ -111,19 +112,28 @@ export const GeoIpForm: React.FC<GeoIpFormProps> = ({
 				setResult(null);
 				setLoading(true);
 				location.hash = `#${data.ip}`;
-				const recaptchaToken = recaptchaFn ? await recaptchaFn() : undefined;
-				const headers: Record<string, string> = {};
-				if (recaptchaToken !== undefined) {
-					headers["X-Recaptcha-Token"] = recaptchaToken;
+				const lookup = async (force: boolean) => {
+					const captchaToken = captchaFn ? await captchaFn(force) : undefined;
+					const headers: Record<string, string> = {};
+					if (captchaToken !== undefined) {
+						headers["X-Captcha-Token"] = captchaToken;
+					}
+					const res = await api.lookupGeoIp({
+						query: {
+							ip: data.ip,
+							edition,
+							locale,
+						},
+						headers,
+					});
+					return {res, captchaToken};
+				};
+				const first = await lookup(false);
+				let res = first.res;
+				if (res.response.status === 401 && captchaFn && first.captchaToken === undefined) {
+					// Session has expired or was never issued
+					res = (await lookup(true)).res;
 				}
-				const res = await api.lookupGeoIp({
-					query: {
-						ip: data.ip,
-						edition,
-						locale,
-					},
-					headers,
-				});
 				if (res.error) {
 					setError(res.error.error ?? `Error ${res.response.status}`);
 					return;
@@ -135,7 +145,7 @@ export const GeoIpForm: React.FC<GeoIpFormProps> = ({
 				setLoading(false);
 			}
 		})(evt);
-	}, [edition, locale, recaptchaFn]);
+	}, [edition, locale, captchaFn]);
 
 	const handleEditionChange = useCallback((evt: React.ChangeEvent<HTMLSelectElement>) => {
 		setEdition(evt.target.value);
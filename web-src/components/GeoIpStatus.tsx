@@ -1,6 +1,7 @@
 import React from "react";
 import * as api from "../client";
 import { GeoIpDatabaseStatus } from "./GeoIpDatabaseStatus.tsx";
+import { TimezoneDatabaseStatus } from "./TimezoneDatabaseStatus.tsx";
 
 export interface GeoIpStatusProps {
 	status: api.GeoIpStatus;
@@ -14,6 +15,7 @@ export const GeoIpStatus: React.FC<GeoIpStatusProps> = ({status}) => {
 					<GeoIpDatabaseStatus key={database.edition} database={database} />
 				))
 			}
+			<TimezoneDatabaseStatus timezones={status.timezones} />
 		</div>
 	);
 };
@@ -0,0 +1,49 @@
+import React from "react";
+import * as api from "../client";
+
+export interface TimezoneDatabaseStatusProps {
+	timezones: api.TimezoneDatabaseStatus;
+}
+
+export const TimezoneDatabaseStatus: React.FC<TimezoneDatabaseStatusProps> = ({timezones}) => {
+	return (
+		<div className="card bg-base-200 shadow-sm">
+			<div className="card-body">
+				<h2 className="card-title">Timezones</h2>
+				<table className="table table-sm">
+					<tbody>
+						<tr>
+							<th>Source</th>
+							<td>{timezones.source === "archive" ? "tzdata archive" : "System zoneinfo"}</td>
+						</tr>
+						<tr>
+							<th>Version</th>
+							<td>{timezones.version ?? "-"}</td>
+						</tr>
+						<tr>
+							<th>Last update check</th>
+							<td>{timezones.last_update_check ?? "-"}</td>
+						</tr>
+						<tr>
+							<th>Timestamp</th>
+							<td>{timezones.timestamp ?? "-"}</td>
+						</tr>
+						<tr>
+							<th>Zones</th>
+							<td>{timezones.zones}</td>
+						</tr>
+						<tr>
+							<th>Links</th>
+							<td>{timezones.links}</td>
+						</tr>
+					</tbody>
+				</table>
+				{timezones.error !== undefined && (
+					<div className="alert alert-error">
+						{timezones.error}
+					</div>
+				)}
+			</div>
+		</div>
+	);
+};